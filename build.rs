@@ -3,9 +3,8 @@ use std::{env, path::PathBuf};
 fn main() {
     let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
 
-    match target_os.as_str() {
-        "macos" => compile_macos(),
-        _ => {}
+    if target_os == "macos" {
+        compile_macos();
     }
 }
 
@@ -0,0 +1,268 @@
+//! Restricting which devices may be written to at all, for unattended setups
+//! like flashing rigs, where writing anywhere else would be a disaster.
+//!
+//! An allowlist is a file with one device per line, like:
+//!
+//! ```text
+//! # The SD muxes on the rig
+//! serial=ABC123
+//! by-id=/dev/disk/by-id/usb-Generic_SD_MUX_000000000001-0:0
+//! by-path=/dev/disk/by-path/platform-xhci-hcd.1-usb-0:1:1.0-scsi-0:0:0:0
+//! ```
+//!
+//! The system-wide allowlist applies to every write, and is checked again by
+//! the escalated processes themselves, so it can't be skipped by a buggy or
+//! misconfigured caller. A user's own allowlist can only narrow it further.
+//! It is only checked before escalating, since a process running as root
+//! must not read whatever file it's pointed at on a user's behalf.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    device::{Type, WriteTarget},
+    util::user_config_dir,
+};
+
+/// The allowlist that applies to everyone.
+pub const SYSTEM_ALLOWLIST: &str = "/etc/caligula/allowed-targets";
+
+/// The file name of a user's own allowlist, in their config directory.
+const USER_ALLOWLIST: &str = "allowed-targets";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowList {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Serial(String),
+    /// A link in `/dev/disk/by-id` or `/dev/disk/by-path`.
+    Link(PathBuf),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AllowListError {
+    #[error("Could not read allowlist {path}: {error}")]
+    Io { path: PathBuf, error: io::Error },
+    #[error("{path}:{line}: {message}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    #[error("Could not look up {target}: {error}")]
+    Target { target: PathBuf, error: String },
+    #[error("{target} is not in the allowlist {list}")]
+    NotAllowed { target: PathBuf, list: PathBuf },
+}
+
+impl AllowList {
+    pub fn load(path: &Path) -> Result<Self, AllowListError> {
+        let contents = std::fs::read_to_string(path).map_err(|error| AllowListError::Io {
+            path: path.to_owned(),
+            error,
+        })?;
+        Self::parse(path, &contents)
+    }
+
+    fn parse(path: &Path, contents: &str) -> Result<Self, AllowListError> {
+        let mut entries = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| AllowListError::Parse {
+                path: path.to_owned(),
+                line: i + 1,
+                message,
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("expected key=value".into()));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let entry = match key {
+                "serial" => Entry::Serial(value.to_owned()),
+                "by-id" | "by-path" => {
+                    let dir = format!("/dev/disk/{key}/");
+                    if !value.starts_with(&dir) {
+                        return Err(error(format!("{key} should be a link in {dir}")));
+                    }
+                    Entry::Link(value.into())
+                }
+                // Never repeat what's in the file, in case it isn't actually
+                // an allowlist, and we can read something the user can't.
+                _ => {
+                    return Err(error(
+                        "unknown key, expected serial, by-id or by-path".into(),
+                    ))
+                }
+            };
+            entries.push(entry);
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            entries,
+        })
+    }
+
+    /// Whether `target` is in the list. Allowing a disk allows its partitions
+    /// too.
+    pub fn allows(&self, target: &WriteTarget) -> bool {
+        let devnodes: Vec<PathBuf> = [Some(target.devnode.clone()), disk_devnode(target)]
+            .into_iter()
+            .flatten()
+            .filter_map(|p| p.canonicalize().ok())
+            .collect();
+
+        self.entries.iter().any(|e| match e {
+            Entry::Serial(serial) => {
+                target.target_type != Type::File && target.details.serial.as_deref() == Some(serial)
+            }
+            Entry::Link(link) => match link.canonicalize() {
+                Ok(dev) => devnodes.contains(&dev),
+                Err(_) => false,
+            },
+        })
+    }
+}
+
+/// The allowlists that apply to this user, besides the system one.
+pub fn user_allowlists() -> Vec<PathBuf> {
+    user_config_dir()
+        .map(|d| d.join("caligula").join(USER_ALLOWLIST))
+        .filter(|p| p.exists())
+        .into_iter()
+        .collect()
+}
+
+/// Checks that `dest` is in the system allowlist, if there is one, and in
+/// every one of `lists`. A list that is missing or broken allows nothing.
+///
+/// Processes running as root must only pass lists that they trust, not ones
+/// that they were given.
+pub fn check(dest: &Path, lists: &[PathBuf]) -> Result<(), AllowListError> {
+    let system = Path::new(SYSTEM_ALLOWLIST);
+    let lists: Vec<&Path> = system
+        .exists()
+        .then_some(system)
+        .into_iter()
+        .chain(lists.iter().map(PathBuf::as_path))
+        .collect();
+    if lists.is_empty() {
+        return Ok(());
+    }
+
+    let target = WriteTarget::try_from(dest).map_err(|e| AllowListError::Target {
+        target: dest.to_owned(),
+        error: e.to_string(),
+    })?;
+    for path in lists {
+        let list = AllowList::load(path)?;
+        if !list.allows(&target) {
+            return Err(AllowListError::NotAllowed {
+                target: dest.to_owned(),
+                list: list.path,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The disk that a partition is on.
+#[cfg(target_os = "linux")]
+fn disk_devnode(target: &WriteTarget) -> Option<PathBuf> {
+    if target.target_type != Type::Partition {
+        return None;
+    }
+    let sysnode = Path::new("/sys/class/block")
+        .join(&target.name)
+        .canonicalize()
+        .ok()?;
+    Some(Path::new("/dev").join(sysnode.parent()?.file_name()?))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disk_devnode(_target: &WriteTarget) -> Option<PathBuf> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use test_case::test_case;
+
+    use crate::device::{DeviceDetails, Model, Removable, TargetSize, Type, WriteTarget};
+
+    use super::{AllowList, AllowListError, Entry};
+
+    #[test]
+    fn parses_allowlist() {
+        let contents = "# The SD muxes\n\nserial=ABC123\n  by-id = /dev/disk/by-id/usb-SD_MUX-0:0\nby-path=/dev/disk/by-path/platform-usb-0:1:1.0-scsi-0:0:0:0\n";
+
+        let list = AllowList::parse(Path::new("list"), contents).unwrap();
+
+        assert_eq!(
+            list.entries,
+            vec![
+                Entry::Serial("ABC123".into()),
+                Entry::Link("/dev/disk/by-id/usb-SD_MUX-0:0".into()),
+                Entry::Link("/dev/disk/by-path/platform-usb-0:1:1.0-scsi-0:0:0:0".into()),
+            ]
+        );
+    }
+
+    #[test_case("serial" => 1; "no value")]
+    #[test_case("serial=A\nmodel=B" => 2; "unknown key")]
+    #[test_case("by-id=/dev/sdb" => 1; "not a link")]
+    #[test_case("by-path=/dev/disk/by-id/usb-SD_MUX-0:0" => 1; "wrong directory")]
+    fn rejects_bad_allowlist(contents: &str) -> usize {
+        match AllowList::parse(Path::new("list"), contents) {
+            Err(AllowListError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test_case("root:$6$secret:19000:0:99999:7:::"; "no key")]
+    #[test_case("secret=hunter2"; "unknown key")]
+    fn parse_errors_do_not_repeat_contents(contents: &str) {
+        let e = AllowList::parse(Path::new("list"), contents).unwrap_err();
+
+        assert!(!e.to_string().contains("secret"), "{e}");
+    }
+
+    #[test_case("sdb", Type::Disk, Some("ABC123") => true; "serial")]
+    #[test_case("sdb1", Type::Partition, Some("ABC123") => true; "partition of allowed disk")]
+    #[test_case("sda", Type::Disk, Some("S4EVNF0M") => false; "other serial")]
+    #[test_case("sda", Type::Disk, None => false; "no serial")]
+    #[test_case("image.img", Type::File, Some("ABC123") => false; "file")]
+    fn allows(name: &str, target_type: Type, serial: Option<&str>) -> bool {
+        let list = AllowList {
+            path: PathBuf::from("list"),
+            entries: vec![
+                Entry::Serial("ABC123".into()),
+                Entry::Link("/dev/disk/by-id/does-not-exist".into()),
+            ],
+        };
+        let target = WriteTarget {
+            name: name.into(),
+            devnode: PathBuf::from("/dev").join(name),
+            size: TargetSize::from(None),
+            model: Model::from(None),
+            removable: Removable::Yes,
+            target_type,
+            details: DeviceDetails {
+                serial: serial.map(str::to_owned),
+                ..Default::default()
+            },
+        };
+
+        list.allows(&target)
+    }
+}
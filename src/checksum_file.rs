@@ -0,0 +1,480 @@
+//! Parsing of checksum files like `SHA256SUMS` or `image.iso.sha256`, as
+//! published alongside most distro images.
+//!
+//! Both the GNU coreutils format (`<hex>  <name>`, as output by `sha256sum`) and
+//! the BSD format (`SHA256 (<name>) = <hex>`, as output by `sha256sum --tag` or
+//! `openssl dgst`) are supported. Lines that are neither, like the armor of a
+//! clearsigned file or comments, are ignored.
+
+use std::{
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+use crate::hash::HashAlg;
+
+/// A single line of a checksum file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumEntry {
+    /// The algorithm, if the line explicitly states it (i.e. BSD style).
+    pub tagged_alg: Option<HashAlg>,
+    pub digest: Vec<u8>,
+    /// The file name, as written in the checksum file.
+    pub file_name: String,
+}
+
+/// The entry in a checksum file matching some file, along with the
+/// algorithms it could have been made with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMatch {
+    pub algs: Vec<HashAlg>,
+    pub expected_hash: Vec<u8>,
+    pub entry_name: String,
+    /// Index of the candidate name that matched.
+    pub candidate: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ChecksumFileError {
+    #[error("Could not find an entry for any of {0:?} in the checksum file")]
+    NoEntry(Vec<String>),
+    #[error("Entry for {name} has a digest of length {actual_bytes}, but {alg} digests are {expected_bytes} bytes")]
+    InvalidLengthForAlg {
+        name: String,
+        alg: HashAlg,
+        expected_bytes: usize,
+        actual_bytes: usize,
+    },
+    #[error("Could not detect hash algorithm of {name} from digest size {len}")]
+    AlgDetectionFailure { name: String, len: usize },
+}
+
+/// Parses every recognizable entry out of a checksum file.
+pub fn parse_checksum_file(contents: &str) -> Vec<ChecksumEntry> {
+    let mut in_signature = false;
+    let mut entries = vec![];
+
+    for line in contents.lines() {
+        let line = line.trim_end_matches('\r');
+
+        // Skip over the signature block of clearsigned files.
+        match line {
+            "-----BEGIN PGP SIGNATURE-----" => in_signature = true,
+            "-----END PGP SIGNATURE-----" => in_signature = false,
+            _ => {}
+        }
+        if in_signature {
+            continue;
+        }
+
+        if let Some(e) = parse_bsd_line(line).or_else(|| parse_gnu_line(line)) {
+            entries.push(e);
+        }
+    }
+
+    entries
+}
+
+/// Parses `<hex>  <name>` or `<hex> *<name>`, optionally with a leading
+/// backslash signifying that the name is escaped.
+fn parse_gnu_line(line: &str) -> Option<ChecksumEntry> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(l) => (true, l),
+        None => (false, line),
+    };
+
+    let (digest, rest) = line.split_once(' ')?;
+    let digest = parse_hex_digest(digest)?;
+
+    // The second character is ' ' for text mode, and '*' for binary mode.
+    let name = rest.strip_prefix([' ', '*']).unwrap_or(rest);
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(ChecksumEntry {
+        tagged_alg: None,
+        digest,
+        file_name: if escaped {
+            unescape_name(name)
+        } else {
+            name.to_owned()
+        },
+    })
+}
+
+/// Parses `<ALG> (<name>) = <hex>`, or the `openssl dgst` variant
+/// `<ALG>(<name>)= <hex>`.
+fn parse_bsd_line(line: &str) -> Option<ChecksumEntry> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(l) => (true, l),
+        None => (false, line),
+    };
+
+    let (tag, rest) = line.split_once('(')?;
+    let (name, digest) = rest.rsplit_once(')')?;
+    let digest = digest.trim_start().strip_prefix('=')?.trim();
+
+    let tagged_alg = HashAlg::from_tag(tag.trim())?;
+    let digest = parse_hex_digest(digest)?;
+
+    Some(ChecksumEntry {
+        tagged_alg: Some(tagged_alg),
+        digest,
+        file_name: if escaped {
+            unescape_name(name)
+        } else {
+            name.to_owned()
+        },
+    })
+}
+
+fn parse_hex_digest(s: &str) -> Option<Vec<u8>> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    base16::decode(s).ok()
+}
+
+fn unescape_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Guesses the algorithm used from the name of a checksum file, like
+/// `SHA256SUMS`, `image.iso.sha512` or `md5sum.txt`. Only the end of the name
+/// is looked at, since the rest is usually the name of the image, which may
+/// well contain the name of some other algorithm.
+pub fn alg_from_file_name(path: impl AsRef<Path>) -> Option<HashAlg> {
+    let name = path.as_ref().file_name()?.to_string_lossy().to_lowercase();
+    let name = name.strip_suffix(".txt").unwrap_or(&name);
+    let ext = name.rsplit_once('.').map(|(_, ext)| ext);
+
+    HashAlg::ALL
+        .iter()
+        .copied()
+        .filter(|a| {
+            let tool = tool_name(*a);
+            // Like `image.iso.sha256`.
+            ext == Some(a.sri_alg())
+                // Like `image.iso.sha256sum`, `SHA256SUMS` or `md5sum.txt`.
+                || [tool.clone(), format!("{tool}s")]
+                    .iter()
+                    .any(|t| ends_with_word(name, t))
+        })
+        .max_by_key(|a| a.sri_alg().len())
+}
+
+/// The name of the coreutils tool that writes checksum files for `alg`.
+fn tool_name(alg: HashAlg) -> String {
+    match alg {
+        HashAlg::Blake2b512 => "b2sum".into(),
+        HashAlg::Blake3 => "b3sum".into(),
+        other => format!("{}sum", other.sri_alg()),
+    }
+}
+
+/// Whether `name` ends with `word`, and `word` isn't just the end of some
+/// longer word.
+fn ends_with_word(name: &str, word: &str) -> bool {
+    name.strip_suffix(word)
+        .is_some_and(|rest| !rest.ends_with(|c: char| c.is_ascii_alphanumeric()))
+}
+
+/// Finds the entry for a file in a checksum file. Candidates are tried in
+/// order, and entries match if their base name is equal to a candidate.
+///
+/// `file_alg` is the algorithm guessed from the name of the checksum file, if
+/// any.
+pub fn find_entry(
+    entries: &[ChecksumEntry],
+    candidates: &[&str],
+    file_alg: Option<HashAlg>,
+) -> Result<ChecksumMatch, ChecksumFileError> {
+    let (candidate, entry) = candidates
+        .iter()
+        .enumerate()
+        .find_map(|(i, c)| {
+            entries
+                .iter()
+                .find(|e| entry_base_name(&e.file_name) == *c)
+                .map(|e| (i, e))
+        })
+        .ok_or_else(|| {
+            ChecksumFileError::NoEntry(candidates.iter().map(|c| c.to_string()).collect())
+        })?;
+
+    let actual_bytes = entry.digest.len();
+    let algs = match entry.tagged_alg.or(file_alg) {
+        Some(alg) => {
+            let expected_bytes = alg.digest_bytes();
+            if expected_bytes != actual_bytes {
+                return Err(ChecksumFileError::InvalidLengthForAlg {
+                    name: entry.file_name.clone(),
+                    alg,
+                    expected_bytes,
+                    actual_bytes,
+                });
+            }
+            vec![alg]
+        }
+        None => {
+            let algs = HashAlg::detect_from_length(actual_bytes);
+            if algs.is_empty() {
+                return Err(ChecksumFileError::AlgDetectionFailure {
+                    name: entry.file_name.clone(),
+                    len: actual_bytes,
+                });
+            }
+            algs.to_vec()
+        }
+    };
+
+    Ok(ChecksumMatch {
+        algs,
+        expected_hash: entry.digest.clone(),
+        entry_name: entry.file_name.clone(),
+        candidate,
+    })
+}
+
+fn entry_base_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+/// Checksum files are small. Anything bigger than this is not worth reading.
+const MAX_CHECKSUM_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Looks for files in `dir` that might be checksum files for the image, which
+/// may go by any of `image_names` (i.e. with and without a compression
+/// extension). Files specific to the image (i.e. `image.iso.sha256`) are
+/// returned before files that cover many images (i.e. `SHA256SUMS`).
+pub fn discover_checksum_files(dir: impl AsRef<Path>, image_names: &[&str]) -> Vec<PathBuf> {
+    let Ok(dir) = read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut found: Vec<(bool, PathBuf)> = dir
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            e.metadata()
+                .is_ok_and(|m| m.is_file() && m.len() <= MAX_CHECKSUM_FILE_BYTES)
+        })
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            let specific = looks_like_checksum_file(&name, image_names)?;
+            Some((!specific, e.path()))
+        })
+        .collect();
+
+    found.sort();
+    found.into_iter().map(|(_, p)| p).collect()
+}
+
+/// Returns None if the name doesn't look like a checksum file, Some(true) if
+/// it looks like one specific to the image, and Some(false) if it looks like
+/// one that could cover multiple files.
+fn looks_like_checksum_file(name: &str, image_names: &[&str]) -> Option<bool> {
+    let lower = name.to_lowercase();
+
+    // Signatures of checksum files are not checksum files.
+    const SIGNATURE_EXTS: &[&str] = &[".sig", ".asc", ".gpg", ".sign", ".minisig"];
+    if SIGNATURE_EXTS.iter().any(|e| lower.ends_with(e)) {
+        return None;
+    }
+
+    let exts: Vec<String> = image_names
+        .iter()
+        .filter_map(|image_name| name.strip_prefix(image_name)?.strip_prefix('.'))
+        .map(str::to_lowercase)
+        .collect();
+    if !exts.is_empty() {
+        let is_sum_ext = exts.iter().any(|ext| {
+            ext.contains("sum")
+                || ext == "digests"
+                || HashAlg::ALL.iter().any(|a| ext.starts_with(a.sri_alg()))
+        });
+        return is_sum_ext.then_some(true);
+    }
+
+    let is_sum_file =
+        lower.contains("sums") || lower.contains("checksum") || lower.ends_with("sum.txt");
+    is_sum_file.then_some(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::HashAlg;
+
+    use super::{
+        alg_from_file_name, find_entry, looks_like_checksum_file, parse_checksum_file,
+        ChecksumFileError,
+    };
+    use test_case::test_case;
+
+    const SHA256_A: &str = "531a1557d205e09358e16fc4d79911ae4b9e28984bf10dbd7ab42d39f6a10713";
+    const MD5_B: &str = "b7fbc56aaec74706d8fdae71aae7b0ac";
+
+    #[test]
+    fn parse_gnu_style() {
+        let entries = parse_checksum_file(&format!(
+            "{SHA256_A}  image-a.iso\n{MD5_B} *./dir/image-b.img.xz\n"
+        ));
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].tagged_alg, None);
+        assert_eq!(entries[0].file_name, "image-a.iso");
+        assert_eq!(entries[0].digest, base16::decode(SHA256_A).unwrap());
+        assert_eq!(entries[1].file_name, "./dir/image-b.img.xz");
+        assert_eq!(entries[1].digest, base16::decode(MD5_B).unwrap());
+    }
+
+    #[test_case("SHA256 (image-a.iso) = {}"; "coreutils tag")]
+    #[test_case("SHA256(image-a.iso)= {}"; "openssl")]
+    #[test_case("SHA2-256(image-a.iso)= {}"; "openssl 3")]
+    fn parse_bsd_style(format: &str) {
+        let line = format.replace("{}", SHA256_A);
+        let entries = parse_checksum_file(&line);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].tagged_alg, Some(HashAlg::Sha256));
+        assert_eq!(entries[0].file_name, "image-a.iso");
+        assert_eq!(entries[0].digest, base16::decode(SHA256_A).unwrap());
+    }
+
+    #[test]
+    fn parse_escaped_name() {
+        let entries = parse_checksum_file(&format!("\\{SHA256_A}  weird\\nname\\\\.iso"));
+
+        assert_eq!(entries[0].file_name, "weird\nname\\.iso");
+    }
+
+    #[test]
+    fn parse_clearsigned_fedora_style() {
+        let contents = format!(
+            "-----BEGIN PGP SIGNED MESSAGE-----\n\
+            Hash: SHA256\n\
+            \n\
+            # image-a.iso: 1234 bytes\n\
+            SHA256 (image-a.iso) = {SHA256_A}\n\
+            -----BEGIN PGP SIGNATURE-----\n\
+            \n\
+            iQIzBAEBCAAdFiEE {MD5_B}\n\
+            -----END PGP SIGNATURE-----\n"
+        );
+        let entries = parse_checksum_file(&contents);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file_name, "image-a.iso");
+    }
+
+    #[test_case("SHA256SUMS" => Some(HashAlg::Sha256))]
+    #[test_case("image.iso.sha512" => Some(HashAlg::Sha512))]
+    #[test_case("md5sum.txt" => Some(HashAlg::Md5))]
+    #[test_case("/some/dir/sha1sums" => Some(HashAlg::Sha1))]
+    #[test_case("SHA3-256SUMS" => Some(HashAlg::Sha3_256))]
+    #[test_case("B2SUMS" => Some(HashAlg::Blake2b512))]
+    #[test_case("image.iso.b3sum" => Some(HashAlg::Blake3))]
+    #[test_case("image.iso.sha256sum" => Some(HashAlg::Sha256))]
+    #[test_case("sha1-tools-sha256sums.txt" => Some(HashAlg::Sha256))]
+    #[test_case("md5-image.iso.sha512" => Some(HashAlg::Sha512))]
+    #[test_case("blake3-SHA512SUMS" => Some(HashAlg::Sha512))]
+    #[test_case("sha256-image.iso" => None)]
+    #[test_case("CHECKSUM" => None)]
+    fn guess_alg_from_file_name(name: &str) -> Option<HashAlg> {
+        alg_from_file_name(name)
+    }
+
+    #[test]
+    fn find_entry_by_base_name() {
+        let entries = parse_checksum_file(&format!(
+            "{MD5_B}  other.iso\n{SHA256_A}  ./images/image-a.iso\n"
+        ));
+
+        let found = find_entry(&entries, &["image-a.iso"], None).unwrap();
+
+        assert_eq!(
+            found.algs,
+            vec![HashAlg::Sha256, HashAlg::Sha3_256, HashAlg::Blake3]
+        );
+        assert_eq!(found.expected_hash, base16::decode(SHA256_A).unwrap());
+        assert_eq!(found.entry_name, "./images/image-a.iso");
+        assert_eq!(found.candidate, 0);
+    }
+
+    #[test]
+    fn find_entry_tries_candidates_in_order() {
+        let entries = parse_checksum_file(&format!(
+            "{MD5_B}  image-a.iso\n{SHA256_A}  image-a.iso.xz\n"
+        ));
+
+        let found = find_entry(&entries, &["image-a.iso.xz", "image-a.iso"], None).unwrap();
+
+        assert_eq!(found.entry_name, "image-a.iso.xz");
+        assert_eq!(found.candidate, 0);
+    }
+
+    #[test]
+    fn find_entry_missing() {
+        let entries = parse_checksum_file(&format!("{SHA256_A}  image-a.iso\n"));
+
+        let err = find_entry(&entries, &["image-b.iso"], None).unwrap_err();
+
+        assert_eq!(err, ChecksumFileError::NoEntry(vec!["image-b.iso".into()]));
+    }
+
+    #[test]
+    fn find_entry_length_mismatch_with_file_alg() {
+        let entries = parse_checksum_file(&format!("{MD5_B}  image-a.iso\n"));
+
+        let err = find_entry(&entries, &["image-a.iso"], Some(HashAlg::Sha256)).unwrap_err();
+
+        assert_eq!(
+            err,
+            ChecksumFileError::InvalidLengthForAlg {
+                name: "image-a.iso".into(),
+                alg: HashAlg::Sha256,
+                expected_bytes: 32,
+                actual_bytes: 16
+            }
+        );
+    }
+
+    #[test_case("image.iso.sha256" => Some(true); "specific alg")]
+    #[test_case("image.iso.sha256sum" => Some(true); "specific alg sum")]
+    #[test_case("image.iso.md5" => Some(true); "specific md5")]
+    #[test_case("image.iso.DIGESTS" => Some(true); "specific digests")]
+    #[test_case("SHA256SUMS" => Some(false); "sums")]
+    #[test_case("CHECKSUM" => Some(false); "fedora checksum")]
+    #[test_case("Fedora-Workstation-40-x86_64-CHECKSUM" => Some(false); "fedora long checksum")]
+    #[test_case("sha256sum.txt" => Some(false); "sum txt")]
+    #[test_case("SHA256SUMS.gpg" => None; "signature")]
+    #[test_case("image.iso.sha256.asc" => None; "specific signature")]
+    #[test_case("image.iso.xz" => None; "compressed image")]
+    #[test_case("image.iso" => None; "image itself")]
+    #[test_case("README.md" => None; "unrelated")]
+    fn checksum_file_names(name: &str) -> Option<bool> {
+        looks_like_checksum_file(name, &["image.iso"])
+    }
+
+    #[test_case("image.iso.xz.sha256" => Some(true); "compressed")]
+    #[test_case("image.iso.sha256" => Some(true); "decompressed")]
+    #[test_case("image.iso.xz" => None; "image itself")]
+    #[test_case("image.iso" => None; "decompressed image")]
+    fn checksum_file_names_for_compressed_image(name: &str) -> Option<bool> {
+        looks_like_checksum_file(name, &["image.iso.xz", "image.iso"])
+    }
+}
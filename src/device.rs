@@ -9,15 +9,37 @@ use bytesize::ByteSize;
 use serde::{Deserialize, Serialize};
 use valuable::Valuable;
 
+use crate::{
+    mounts::{holders_of, Holder, MountTable},
+    partitions::{partitions_of, Partition},
+    system_disks::SystemDisks,
+};
+
 #[cfg(target_os = "linux")]
 pub fn enumerate_devices() -> impl Iterator<Item = WriteTarget> {
     use std::fs::read_dir;
 
     let paths = read_dir("/sys/class/block").unwrap();
+    // Loaded once for every device, since it means going through every
+    // mount on the system.
+    let system_disks = load_system_disks();
 
     paths
         .filter_map(|r| r.ok())
-        .filter_map(|d| WriteTarget::try_from(d.path().as_ref()).ok())
+        .filter_map(move |d| WriteTarget::from_dev_name(&d.file_name(), &system_disks).ok())
+}
+
+/// Loads the system disks for marking devices with. If that fails, no device
+/// is marked, rather than none being listed at all. The writer loads them
+/// again itself, and refuses to write if it can't.
+#[cfg(target_os = "linux")]
+fn load_system_disks() -> SystemDisks {
+    use tracing::warn;
+
+    SystemDisks::load().unwrap_or_else(|e| {
+        warn!("Could not find out which disks the system is on: {e}");
+        SystemDisks::default()
+    })
 }
 
 #[cfg(target_os = "macos")]
@@ -82,6 +104,7 @@ pub fn enumerate_devices() -> impl Iterator<Item = WriteTarget> {
                 model,
                 removable,
                 target_type,
+                details: DeviceDetails::default(),
             })
         }
 
@@ -102,6 +125,7 @@ pub struct WriteTarget {
     pub model: Model,
     pub removable: Removable,
     pub target_type: Type,
+    pub details: DeviceDetails,
 }
 
 impl WriteTarget {
@@ -127,7 +151,7 @@ impl WriteTarget {
     }
 
     #[cfg(target_os = "linux")]
-    fn from_dev_name(name: &OsStr) -> Result<Self, DeviceParseError> {
+    fn from_dev_name(name: &OsStr, system_disks: &SystemDisks) -> Result<Self, DeviceParseError> {
         use std::fs::read_to_string;
 
         fn read_sys_file(p: impl AsRef<Path>) -> Result<Option<String>, std::io::Error> {
@@ -152,6 +176,18 @@ impl WriteTarget {
         }
 
         let sysnode = PathBuf::from("/sys/class/block").join(name);
+        let target_type = match sysnode.join("partition").exists() {
+            true => Type::Partition,
+            false => Type::Disk,
+        };
+
+        // Most of the details only exist on the whole disk, not on its
+        // partitions, which sit one directory below it.
+        let canonical_sysnode = sysnode.canonicalize()?;
+        let disk_sysnode = match target_type {
+            Type::Partition => canonical_sysnode.parent().unwrap_or(&canonical_sysnode),
+            _ => &canonical_sysnode,
+        };
 
         let removable = match read_sys_file(sysnode.join("removable"))?
             .as_ref()
@@ -168,11 +204,65 @@ impl WriteTarget {
         );
 
         let model =
-            Model(read_sys_file(sysnode.join("device/model"))?.map(|m| m.trim().to_owned()));
+            Model(read_sys_file(disk_sysnode.join("device/model"))?.map(|m| m.trim().to_owned()));
 
-        let target_type = match sysnode.join("partition").exists() {
-            true => Type::Partition,
-            false => Type::Disk,
+        let read_flag = |p: PathBuf| -> Result<Option<bool>, std::io::Error> {
+            Ok(match read_sys_file(p)?.as_deref() {
+                Some("0") => Some(false),
+                Some("1") => Some(true),
+                _ => None,
+            })
+        };
+        let read_number = |p: &str| -> Result<Option<u64>, std::io::Error> {
+            Ok(read_sys_file(disk_sysnode.join(p))?.and_then(|s| s.parse().ok()))
+        };
+        let non_empty = |s: Option<String>| s.filter(|s| !s.is_empty());
+
+        // USB mass storage doesn't expose the serial number on the disk
+        // itself, only on the USB device it hangs off of.
+        let serial = match non_empty(read_sys_file(disk_sysnode.join("device/serial"))?) {
+            Some(s) => Some(s),
+            None => match disk_sysnode
+                .ancestors()
+                .take_while(|p| p.starts_with("/sys/devices"))
+                .find(|p| p.join("serial").is_file() && p.join("idVendor").is_file())
+            {
+                Some(usb) => non_empty(read_sys_file(usb.join("serial"))?),
+                None => None,
+            },
+        };
+
+        let wwn = match non_empty(read_sys_file(disk_sysnode.join("wwid"))?) {
+            Some(w) => Some(w),
+            None => non_empty(read_sys_file(disk_sysnode.join("device/wwid"))?),
+        };
+
+        let details = DeviceDetails {
+            // Virtio and NVMe give a numeric PCI vendor ID here, which means
+            // nothing to anyone.
+            vendor: non_empty(read_sys_file(disk_sysnode.join("device/vendor"))?)
+                .filter(|v| !v.starts_with("0x")),
+            serial,
+            wwn,
+            transport: Transport::from_sys_path(disk_sysnode),
+            // A partition can be made read-only on its own, and is read-only
+            // whenever its disk is.
+            read_only: match (
+                read_flag(sysnode.join("ro"))?,
+                read_flag(disk_sysnode.join("ro"))?,
+            ) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (own, _) => own,
+            },
+            rotational: read_flag(disk_sysnode.join("queue/rotational"))?,
+            logical_block_size: read_number("queue/logical_block_size")?,
+            physical_block_size: read_number("queue/physical_block_size")?,
+            diskseq: read_number("diskseq")?,
+            by_id: find_by_id_link(&devnode),
+            system_disk: system_disks.contains(&devnode)?,
+            holders: holders_of(&devnode)?,
+            partition_table: None,
+            partitions: vec![],
         };
 
         Ok(Self {
@@ -182,6 +272,7 @@ impl WriteTarget {
             removable,
             model,
             target_type,
+            details,
         })
     }
 
@@ -193,13 +284,131 @@ impl WriteTarget {
             model: Model(None),
             removable: Removable::Unknown,
             target_type: Type::File,
+            details: DeviceDetails::default(),
         })
     }
+
+    /// Looks up what's on the device, so that the user can recognize it. See
+    /// [partitions_of] for what `read_disk` means.
+    pub fn load_partitions(&mut self, mounts: &MountTable, read_disk: bool) -> io::Result<()> {
+        if self.target_type == Type::File {
+            return Ok(());
+        }
+        let (partition_table, partitions) = partitions_of(&self.name, mounts, read_disk)?;
+        self.details.partition_table = partition_table;
+        self.details.partitions = partitions;
+        Ok(())
+    }
+
+    /// What this device was when the user confirmed it, to check that it's
+    /// still the same one when the writer opens it.
+    pub fn identity(&self) -> DeviceIdentity {
+        DeviceIdentity {
+            serial: self.details.serial.clone(),
+            model: self.model.0.clone(),
+            size: self.size.bytes(),
+            diskseq: self.details.diskseq,
+        }
+    }
+
+    /// Looks up the block device that `file` is open on. Files that aren't
+    /// block devices give [None].
+    #[cfg(target_os = "linux")]
+    pub fn of_open_file(file: &std::fs::File) -> Result<Option<Self>, DeviceParseError> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        let meta = file.metadata()?;
+        if !meta.file_type().is_block_device() {
+            return Ok(None);
+        }
+        let sysnode = PathBuf::from(format!(
+            "/sys/dev/block/{}:{}",
+            libc::major(meta.rdev()),
+            libc::minor(meta.rdev())
+        ))
+        .canonicalize()?;
+        match sysnode.file_name() {
+            Some(name) => Ok(Some(Self::from_dev_name(name, &load_system_disks())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// There's no cheap way to get from a file back to the disk here.
+    #[cfg(not(target_os = "linux"))]
+    pub fn of_open_file(_file: &std::fs::File) -> Result<Option<Self>, DeviceParseError> {
+        Ok(None)
+    }
+}
+
+/// The attributes that tell one device apart from another that was plugged
+/// in under the same name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub struct DeviceIdentity {
+    pub serial: Option<String>,
+    pub model: Option<String>,
+    pub size: Option<u64>,
+    pub diskseq: Option<u64>,
+}
+
+impl DeviceIdentity {
+    /// Describes how `actual` differs from this.
+    pub fn differences(&self, actual: &Self) -> Vec<String> {
+        fn show<T: Display>(v: &Option<T>) -> String {
+            match v {
+                Some(v) => v.to_string(),
+                None => "[unknown]".into(),
+            }
+        }
+        fn compare<T: Display + PartialEq>(
+            out: &mut Vec<String>,
+            what: &str,
+            expected: &Option<T>,
+            actual: &Option<T>,
+        ) {
+            if expected != actual {
+                out.push(format!(
+                    "{what}: expected {}, found {}",
+                    show(expected),
+                    show(actual)
+                ));
+            }
+        }
+
+        let mut out = vec![];
+        compare(&mut out, "serial", &self.serial, &actual.serial);
+        compare(&mut out, "model", &self.model, &actual.model);
+        compare(&mut out, "size", &self.size, &actual.size);
+        compare(&mut out, "diskseq", &self.diskseq, &actual.diskseq);
+        out
+    }
+}
+
+/// Finds the most descriptive `/dev/disk/by-id` link to `devnode`, if any.
+#[cfg(target_os = "linux")]
+fn find_by_id_link(devnode: &Path) -> Option<PathBuf> {
+    let Ok(entries) = std::fs::read_dir("/dev/disk/by-id") else {
+        return None;
+    };
+
+    let mut links: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.canonicalize().is_ok_and(|c| c == devnode))
+        .collect();
+
+    // The wwn- and eui links are stable but they don't tell a human anything,
+    // unlike the ones named after the bus, model and serial.
+    links.sort_by_key(|p| {
+        let name = p.file_name().unwrap_or_default().to_string_lossy();
+        let opaque = name.starts_with("wwn-") || name.starts_with("nvme-eui.");
+        (opaque, name.into_owned())
+    });
+    links.into_iter().next()
 }
 
 impl PartialOrd for WriteTarget {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.devnode.partial_cmp(&other.devnode)
+        Some(self.cmp(other))
     }
 }
 
@@ -213,10 +422,20 @@ impl TryFrom<&Path> for WriteTarget {
     type Error = DeviceParseError;
 
     fn try_from(value: &Path) -> Result<Self, Self::Error> {
+        // Links like /dev/disk/by-id/... are named after what they point to,
+        // not after the device itself.
+        #[cfg(target_os = "linux")]
+        let resolved = match value.starts_with("/dev") {
+            true => value.canonicalize().ok(),
+            false => None,
+        };
+        #[cfg(target_os = "linux")]
+        let value = resolved.as_deref().unwrap_or(value);
+
         #[cfg(target_os = "linux")]
         if value.starts_with("/sys/class/block") || value.starts_with("/dev") {
             if let Some(n) = value.file_name() {
-                return Ok(Self::from_dev_name(n)?);
+                return Self::from_dev_name(n, &load_system_disks());
             }
         }
 
@@ -227,7 +446,7 @@ impl TryFrom<&Path> for WriteTarget {
             }
         }
 
-        Ok(Self::from_normal_file(value.to_owned())?)
+        Self::from_normal_file(value.to_owned())
     }
 }
 
@@ -243,6 +462,12 @@ pub enum DeviceParseError {
 #[derive(Debug, Clone, PartialEq, Eq, derive_more::From)]
 pub struct Model(Option<String>);
 
+impl Model {
+    pub fn as_deref(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
 impl Display for Model {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self.0 {
@@ -255,6 +480,12 @@ impl Display for Model {
 #[derive(Debug, Clone, PartialEq, Eq, derive_more::From)]
 pub struct TargetSize(Option<ByteSize>);
 
+impl TargetSize {
+    pub fn bytes(&self) -> Option<u64> {
+        self.0.map(|s| s.as_u64())
+    }
+}
+
 impl Display for TargetSize {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.0 {
@@ -295,6 +526,91 @@ impl Display for Removable {
     }
 }
 
+/// Details that help tell devices apart. Everything here is best-effort, and
+/// only filled in on Linux.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DeviceDetails {
+    pub vendor: Option<String>,
+    pub serial: Option<String>,
+    /// The World Wide Name, or whatever unique ID the device reports instead.
+    pub wwn: Option<String>,
+    pub transport: Transport,
+    pub read_only: Option<bool>,
+    pub rotational: Option<bool>,
+    pub logical_block_size: Option<u64>,
+    pub physical_block_size: Option<u64>,
+    /// A number that the kernel never reuses for another disk until reboot,
+    /// even if it gets the same name.
+    pub diskseq: Option<u64>,
+    /// A stable `/dev/disk/by-id` path to the device.
+    pub by_id: Option<PathBuf>,
+    /// Whether the running system is on this device.
+    pub system_disk: bool,
+    /// RAID arrays, LVM volumes and the like that are using this device.
+    pub holders: Vec<Holder>,
+    /// The type of partition table on the device, like `gpt` or `dos`.
+    pub partition_table: Option<String>,
+    /// What's on the device now, so that users can recognize it.
+    pub partitions: Vec<Partition>,
+}
+
+/// How the device is connected.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Usb,
+    Mmc,
+    Nvme,
+    Sata,
+    Virtio,
+    #[default]
+    Unknown,
+}
+
+impl Transport {
+    /// Guesses the transport from the canonical sysfs path of a block device,
+    /// which goes through every bus between it and the CPU.
+    pub fn from_sys_path(path: &Path) -> Self {
+        let components: Vec<_> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        let has = |prefix: &str| components.iter().any(|c| c.starts_with(prefix));
+
+        // USB goes first, since USB card readers and NVMe enclosures also
+        // look like the thing inside of them.
+        if has("usb") {
+            Self::Usb
+        } else if has("mmc") {
+            Self::Mmc
+        } else if has("nvme") {
+            Self::Nvme
+        } else if has("ata") {
+            Self::Sata
+        } else if has("virtio") {
+            Self::Virtio
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Transport::Usb => "usb",
+                Transport::Mmc => "mmc",
+                Transport::Nvme => "nvme",
+                Transport::Sata => "sata",
+                Transport::Virtio => "virtio",
+                Transport::Unknown => "unknown",
+            }
+        )
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Valuable)]
 pub enum Type {
     File,
@@ -315,3 +631,65 @@ impl Display for Type {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use test_case::test_case;
+
+    use super::{DeviceIdentity, Transport};
+
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb"
+        => Transport::Usb; "usb stick"
+    )]
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:14.0/usb2/2-2/2-2:1.0/host7/target7:0:0/7:0:0:0/block/sdc/sdc1"
+        => Transport::Usb; "usb partition"
+    )]
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda"
+        => Transport::Sata; "sata"
+    )]
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:1d.0/0000:3d:00.0/nvme/nvme0/nvme0n1"
+        => Transport::Nvme; "nvme"
+    )]
+    #[test_case(
+        "/sys/devices/platform/fe320000.mmc/mmc_host/mmc1/mmc1:aaaa/block/mmcblk1"
+        => Transport::Mmc; "sd card"
+    )]
+    #[test_case(
+        "/sys/devices/pci0000:00/0000:00:05.0/virtio2/block/vda"
+        => Transport::Virtio; "virtio"
+    )]
+    #[test_case("/sys/devices/virtual/block/loop0" => Transport::Unknown; "loop device")]
+    fn transport_from_sys_path(path: &str) -> Transport {
+        Transport::from_sys_path(Path::new(path))
+    }
+
+    #[test]
+    fn identity_differences() {
+        let confirmed = DeviceIdentity {
+            serial: Some("ABC123".into()),
+            model: Some("Extreme".into()),
+            size: Some(32_000_000_000),
+            diskseq: Some(12),
+        };
+        let replaced = DeviceIdentity {
+            serial: None,
+            diskseq: Some(13),
+            ..confirmed.clone()
+        };
+
+        assert_eq!(confirmed.differences(&confirmed), Vec::<String>::new());
+        assert_eq!(
+            confirmed.differences(&replaced),
+            vec![
+                "serial: expected ABC123, found [unknown]".to_owned(),
+                "diskseq: expected 12, found 13".to_owned(),
+            ]
+        );
+    }
+}
@@ -21,10 +21,12 @@ use tracing_unwrap::ResultExt;
 use valuable::Valuable;
 
 use crate::{
+    allowlist,
     childproc_common::child_init,
     escalated_daemon::ipc::{EscalatedDaemonInitConfig, SpawnWriter},
-    ipc_common::read_msg_async,
+    ipc_common::{read_msg_async, write_msg_async},
     run_mode::make_writer_spawn_command,
+    writer_process::ipc::{ErrorType, StatusMessage},
 };
 
 pub mod ipc;
@@ -53,6 +55,14 @@ async fn event_loop(socket: &str, mut stream: impl AsyncBufRead + Unpin) -> anyh
         let msg = read_msg_async::<SpawnWriter>(&mut stream).await?;
         info!(msg = msg.as_value(), "Received SpawnWriter request");
 
+        // We're running as root on behalf of someone else, so we don't take
+        // their word for it that the target is allowed.
+        if let Err(e) = allowlist::check(&msg.init_config.dest, &[]) {
+            error!("Refusing to spawn writer: {e}");
+            refuse_spawn(socket, ErrorType::from(e)).await?;
+            continue;
+        }
+
         let command =
             make_writer_spawn_command(socket.into(), msg.log_file.into(), &msg.init_config);
         let mut cmd = tokio::process::Command::from(command);
@@ -73,3 +83,10 @@ async fn event_loop(socket: &str, mut stream: impl AsyncBufRead + Unpin) -> anyh
         );
     }
 }
+
+/// Answers in place of the writer that we didn't spawn, so that the UI isn't
+/// left waiting for it.
+async fn refuse_spawn(socket: &str, error: ErrorType) -> anyhow::Result<()> {
+    let stream = LocalSocketStream::connect(socket.to_fs_name::<GenericFilePath>()?).await?;
+    write_msg_async(stream, &StatusMessage::Error(error)).await
+}
@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt::Display};
 
 use itertools::Itertools;
 use shell_words::{join, quote};
@@ -48,7 +48,7 @@ impl EscalationMethod {
         }
     }
 
-    pub fn wrap_command<'a>(&self, cmd: &Command) -> Command {
+    pub fn wrap_command(&self, cmd: &Command) -> Command<'static> {
         let raw = cmd.to_string();
 
         match self {
@@ -77,18 +77,18 @@ impl EscalationMethod {
     }
 }
 
-impl ToString for Command<'_> {
-    fn to_string(&self) -> String {
+impl Display for Command<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let args = join([&self.proc].into_iter().chain(self.args.iter()));
 
         if self.envs.is_empty() {
-            args
+            write!(f, "{args}")
         } else {
             let envs: String = (self.envs.iter())
                 .map(|(k, v)| format!("{}={}", quote(k), quote(v)))
                 .join(" ");
 
-            format!("{envs} {args}")
+            write!(f, "{envs} {args}")
         }
     }
 }
@@ -1,8 +1,11 @@
 use base64::Engine;
-use digest::Digest;
+use digest::{
+    consts::{U32, U4},
+    Digest, FixedOutput, HashMarker, Output, OutputSizeUser, Update,
+};
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
-use std::io::Read;
+use std::io::{self, Read, Seek, SeekFrom};
 use valuable::Valuable;
 
 macro_rules! generate {
@@ -24,25 +27,67 @@ macro_rules! generate {
             )*)*
         }
 
-        /// Represents a hashing operation in progress.
-        /// This is mostly useful to make a cute progress bar.
-        pub struct Hashing<R>
-        where
-            R : Read,
-        {
-            inner: HashingInner<R>
-        }
-
-        enum HashingInner<R>
-        where
-            R : Read,
-        {
+        /// A hash being computed incrementally, for when it's more convenient
+        /// to push data into it than to have it pull from a reader.
+        #[derive(Clone)]
+        pub enum Hasher {
             $($(
-                $enumarm(GenericHashing<$hash_inner, R>),
+                $enumarm($hash_inner),
             )*)*
         }
 
+        impl Hasher {
+            pub fn new(alg: HashAlg) -> Self {
+                match alg {
+                    $($(
+                        HashAlg::$enumarm => Self::$enumarm($makehash_expr),
+                    )*)*
+                }
+            }
+
+            pub fn alg(&self) -> HashAlg {
+                match self {
+                    $($(
+                        Self::$enumarm(_) => HashAlg::$enumarm,
+                    )*)*
+                }
+            }
+
+            #[inline]
+            pub fn update(&mut self, data: &[u8]) {
+                match self {
+                    $($(
+                        Self::$enumarm(h) => Digest::update(h, data),
+                    )*)*
+                }
+            }
+
+            pub fn finalize(self) -> Vec<u8> {
+                match self {
+                    $($(
+                        Self::$enumarm(h) => h.finalize()[..].into(),
+                    )*)*
+                }
+            }
+        }
+
         impl HashAlg {
+            /// Every supported hash algorithm.
+            pub const ALL: &'static [Self] = &[
+                $($(
+                    Self::$enumarm,
+                )*)*
+            ];
+
+            /// Returns the SRI algorithm prefix of this algorithm.
+            pub fn sri_alg(&self) -> &'static str {
+                match self {
+                    $($(
+                        Self::$enumarm => $sri_prefix,
+                    )*)*
+                }
+            }
+
             /// Parses from SRI algorithm prefix. See https://www.w3.org/TR/SRI/ for more information.
             /// Note that although SRI only supports sha256, sha384, and sha512, we parse out
             /// more than that, so it's not actually to spec, but who cares.
@@ -89,62 +134,15 @@ macro_rules! generate {
                 }
             }
         }
-
-        impl<R> Hashing<R>
-        where
-            R: Read,
-        {
-            #[inline]
-            pub fn new(alg: HashAlg, r: R, block_size: usize) -> Self {
-                let inner = match alg {
-                    $($(
-                        HashAlg::$enumarm => HashingInner::$enumarm(
-                            GenericHashing::new($makehash_expr, r, block_size)
-                        ),
-                    )*)*
-                };
-
-                Self { inner }
-            }
-
-            #[inline]
-            pub fn finalize(self) -> std::io::Result<FileHashInfo> {
-                match self.inner {
-                    $($(
-                        HashingInner::$enumarm(i) => i.finalize(),
-                    )*)*
-                }
-            }
-
-            #[inline]
-            pub fn get_reader_mut(&mut self) -> &mut R {
-                match &mut self.inner {
-                    $($(
-                        HashingInner::$enumarm(i) => i.get_reader_mut(),
-                    )*)*
-                }
-            }
-        }
-
-        impl<R> Iterator for Hashing<R>
-        where
-            R: Read,
-        {
-            type Item = usize;
-
-            #[inline]
-            fn next(&mut self) -> Option<Self::Item> {
-                match &mut self.inner {
-                    $($(
-                        HashingInner::$enumarm(i) => i.next(),
-                    )*)*
-                }
-            }
-        }
     }
 }
 
 generate! {
+    4 => [
+        "crc32" => Crc32("CRC32"): Crc32 {
+            Crc32::default()
+        }
+    ]
     16 => [
         "md5" => Md5("MD5"): md5::Md5 {
             md5::Md5::new()
@@ -164,27 +162,201 @@ generate! {
         "sha256" => Sha256("SHA-256"): sha2::Sha256 {
             sha2::Sha256::new()
         }
+        "sha3-256" => Sha3_256("SHA3-256"): sha3::Sha3_256 {
+            sha3::Sha3_256::new()
+        }
+        "blake3" => Blake3("BLAKE3"): Blake3 {
+            Blake3::default()
+        }
     ]
     48 => [
         "sha384" => Sha384("SHA-384"): sha2::Sha384 {
             sha2::Sha384::new()
         }
+        "sha3-384" => Sha3_384("SHA3-384"): sha3::Sha3_384 {
+            sha3::Sha3_384::new()
+        }
     ]
     64 => [
         "sha512" => Sha512("SHA-512"): sha2::Sha512 {
             sha2::Sha512::new()
         }
+        "sha3-512" => Sha3_512("SHA3-512"): sha3::Sha3_512 {
+            sha3::Sha3_512::new()
+        }
+        "blake2b512" => Blake2b512("BLAKE2b-512"): blake2::Blake2b512 {
+            blake2::Blake2b512::new()
+        }
     ]
 }
 
+impl HashAlg {
+    /// Parses from the algorithm tags used in BSD-style checksum files, like
+    /// `SHA256`, `SHA2-256` or `MD5`.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let tag = tag.to_lowercase();
+        let tag = match tag.strip_prefix("sha2-") {
+            Some(bits) => format!("sha{bits}"),
+            None => tag,
+        };
+        let tag = match tag.as_str() {
+            // As written by `b2sum --tag` and `cksum -a crc32b --tag`.
+            "blake2b" => "blake2b512".to_owned(),
+            "crc32b" => "crc32".to_owned(),
+            _ => tag.replace('-', ""),
+        };
+
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|a| a.sri_alg().replace('-', "") == tag)
+    }
+
+    /// The other algorithms whose hashes are as long as this one's, so that
+    /// a bare hash could be of either.
+    pub fn same_length(&self) -> Vec<Self> {
+        Self::detect_from_length(self.digest_bytes())
+            .iter()
+            .copied()
+            .filter(|a| a != self)
+            .collect()
+    }
+}
+
+/// The CRC-32 used by zlib and `cksum -a crc32b`, adapted to [Digest]. The
+/// checksum is output big-endian, which is how it's usually written out.
+#[derive(Clone, Default)]
+pub struct Crc32(crc32fast::Hasher);
+
+impl HashMarker for Crc32 {}
+
+impl OutputSizeUser for Crc32 {
+    type OutputSize = U4;
+}
+
+impl Update for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+impl FixedOutput for Crc32 {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(&self.0.finalize().to_be_bytes());
+    }
+}
+
+/// BLAKE3 with its default 32-byte output, adapted to [Digest]. Its state is
+/// much larger than the other hashes' so it's boxed.
+#[derive(Clone, Default)]
+pub struct Blake3(Box<blake3::Hasher>);
+
+impl HashMarker for Blake3 {}
+
+impl OutputSizeUser for Blake3 {
+    type OutputSize = U32;
+}
+
+impl Update for Blake3 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+}
+
+impl FixedOutput for Blake3 {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        out.copy_from_slice(self.0.finalize().as_bytes());
+    }
+}
+
+/// The digests made by several algorithms.
+pub type Digests = Vec<(HashAlg, Vec<u8>)>;
+
+/// Feeds the same data to several hashes at once, so that the data only has to
+/// be read once no matter how many digests of it are wanted.
+#[derive(Clone)]
+pub struct MultiHasher {
+    hashers: Vec<Hasher>,
+}
+
+impl MultiHasher {
+    /// Creates a hasher for each of the given algorithms. Duplicates are only
+    /// hashed once.
+    pub fn new(algs: impl IntoIterator<Item = HashAlg>) -> Self {
+        let mut hashers: Vec<Hasher> = vec![];
+        for alg in algs {
+            if !hashers.iter().any(|h| h.alg() == alg) {
+                hashers.push(Hasher::new(alg));
+            }
+        }
+        Self { hashers }
+    }
+
+    /// Returns true if there are no algorithms to hash with.
+    pub fn is_empty(&self) -> bool {
+        self.hashers.is_empty()
+    }
+
+    #[inline]
+    pub fn update(&mut self, data: &[u8]) {
+        for h in &mut self.hashers {
+            h.update(data);
+        }
+    }
+
+    /// Returns the digests, in the order the algorithms were given.
+    pub fn finalize(self) -> Digests {
+        self.hashers
+            .into_iter()
+            .map(|h| (h.alg(), h.finalize()))
+            .collect()
+    }
+}
+
+impl Update for MultiHasher {
+    fn update(&mut self, data: &[u8]) {
+        MultiHasher::update(self, data)
+    }
+}
+
+/// Passes reads through, hashing everything that was read if there is a hasher.
+pub struct HashRead<'a, R, H = MultiHasher> {
+    inner: R,
+    hasher: Option<&'a mut H>,
+}
+
+impl<'a, R, H> HashRead<'a, R, H> {
+    pub fn new(inner: R, hasher: Option<&'a mut H>) -> Self {
+        Self { inner, hasher }
+    }
+}
+
+impl<R: Read, H: Update> Read for HashRead<'_, R, H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(h) = &mut self.hasher {
+            h.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek, H> Seek for HashRead<'_, R, H> {
+    /// This is only here for reporting progress. Actually seeking around
+    /// would make the hash meaningless.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        debug_assert_eq!(pos, SeekFrom::Current(0));
+        self.inner.seek(pos)
+    }
+}
+
 /// Represents a hashing operation in progress.
 /// This is mostly useful to make a cute progress bar.
-struct GenericHashing<H, R>
+pub struct Hashing<R>
 where
-    H: Digest,
     R: Read,
 {
-    hash: H,
+    hasher: MultiHasher,
     read: R,
     len: usize,
     buf: Vec<u8>,
@@ -193,18 +365,19 @@ where
 
 /// Represents the full results of hashing.
 pub struct FileHashInfo {
+    #[allow(dead_code)]
     pub file_bytes: u64,
-    pub file_hash: Vec<u8>,
+    /// The digests, in the order the algorithms were given.
+    pub file_hashes: Digests,
 }
 
-impl<H, R> GenericHashing<H, R>
+impl<R> Hashing<R>
 where
-    H: Digest,
     R: Read,
 {
-    pub fn new(hash: H, read: R, block_size: usize) -> Self {
+    pub fn new(algs: &[HashAlg], read: R, block_size: usize) -> Self {
         Self {
-            hash,
+            hasher: MultiHasher::new(algs.iter().copied()),
             read,
             len: 0,
             buf: vec![0; block_size],
@@ -221,7 +394,7 @@ where
             Some(e) => Err(e),
             None => Ok(FileHashInfo {
                 file_bytes: self.len as u64,
-                file_hash: self.hash.finalize()[..].into(),
+                file_hashes: self.hasher.finalize(),
             }),
         }
     }
@@ -231,16 +404,15 @@ where
     fn step(&mut self) -> std::io::Result<usize> {
         let read_bytes = self.read.read(&mut self.buf)?;
         if read_bytes > 0 {
-            self.hash.update(&self.buf[..read_bytes]);
+            self.hasher.update(&self.buf[..read_bytes]);
         }
         self.len += read_bytes;
         Ok(read_bytes)
     }
 }
 
-impl<H, R> Iterator for GenericHashing<H, R>
+impl<R> Iterator for Hashing<R>
 where
-    H: Digest,
     R: Read,
 {
     type Item = usize;
@@ -263,8 +435,8 @@ where
 
 pub fn parse_base16_or_base64(s: &str) -> Option<Vec<u8>> {
     base16::decode(s)
-        .or_else(|_| base64::engine::general_purpose::URL_SAFE.decode(&s))
-        .or_else(|_| base64::engine::general_purpose::STANDARD.decode(&s))
+        .or_else(|_| base64::engine::general_purpose::URL_SAFE.decode(s))
+        .or_else(|_| base64::engine::general_purpose::STANDARD.decode(s))
         .ok()
 }
 
@@ -273,11 +445,11 @@ pub fn parse_hash_input(h: &str) -> Result<(Vec<HashAlg>, Vec<u8>), HashParseErr
         return Err(HashParseError::EmptyInput);
     }
 
-    if let Some((alg, hash)) = h.split_once('-') {
+    if let Some((alg, hash)) = split_sri(h) {
         let alg =
             HashAlg::from_sri_alg(alg).ok_or_else(|| HashParseError::UnknownAlg(alg.into()))?;
-        let expected_hash = parse_base16_or_base64(hash)
-            .ok_or_else(|| HashParseError::SRIValueNotBase16OrBase64)?;
+        let expected_hash =
+            parse_base16_or_base64(hash).ok_or(HashParseError::SRIValueNotBase16OrBase64)?;
 
         let expected_bytes = alg.digest_bytes();
         let actual_bytes = expected_hash.len();
@@ -305,6 +477,19 @@ pub fn parse_hash_input(h: &str) -> Result<(Vec<HashAlg>, Vec<u8>), HashParseErr
     Err(HashParseError::UnparseableInput)
 }
 
+/// Splits an SRI-style hash into its algorithm and value. Some algorithm names
+/// contain dashes themselves (like `sha3-256`), so known names are tried first.
+fn split_sri(h: &str) -> Option<(&str, &str)> {
+    HashAlg::ALL
+        .iter()
+        .filter_map(|a| {
+            let value = h.strip_prefix(a.sri_alg())?.strip_prefix('-')?;
+            Some((a.sri_alg(), value))
+        })
+        .max_by_key(|(alg, _)| alg.len())
+        .or_else(|| h.split_once('-'))
+}
+
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
 pub enum HashParseError {
     #[error("Unknown algorithm {0}")]
@@ -331,7 +516,7 @@ pub enum HashParseError {
 mod tests {
     use base64::Engine;
 
-    use crate::hash::HashAlg;
+    use crate::hash::{HashAlg, Hashing};
 
     use super::{parse_hash_input, HashParseError};
     use test_case::test_case;
@@ -376,7 +561,7 @@ mod tests {
         assert_eq!(
             result,
             (
-                vec![HashAlg::Sha384],
+                vec![HashAlg::Sha384, HashAlg::Sha3_384],
                 base64::engine::general_purpose::STANDARD
                     .decode("EVSTQN3/azprG1Anm3QDgpJLIm9Nao0Yz1ztcQTwFspd3yD65VohhpuuCOmLASjC")
                     .unwrap()
@@ -393,13 +578,76 @@ mod tests {
         assert_eq!(
             result,
             (
-                vec![HashAlg::Sha256],
+                vec![HashAlg::Sha256, HashAlg::Sha3_256, HashAlg::Blake3],
                 base16::decode("531a1557d205e09358e16fc4d79911ae4b9e28984bf10dbd7ab42d39f6a10713")
                     .unwrap()
             )
         );
     }
 
+    #[test]
+    fn parse_sri_alg_with_dash() {
+        let result = parse_hash_input(
+            "sha3-256-a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a",
+        )
+        .unwrap();
+
+        assert_eq!(
+            result,
+            (
+                vec![HashAlg::Sha3_256],
+                base16::decode("a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a")
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test_case(HashAlg::Crc32, b"123456789" => "cbf43926"; "crc32")]
+    #[test_case(HashAlg::Sha3_256, b"" => "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"; "sha3-256")]
+    #[test_case(HashAlg::Blake3, b"" => "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"; "blake3")]
+    #[test_case(HashAlg::Blake2b512, b"" => "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce"; "blake2b-512")]
+    fn hash_known_answer(alg: HashAlg, data: &[u8]) -> String {
+        let mut hashing = Hashing::new(&[alg], data, 4);
+        for _ in &mut hashing {}
+        base16::encode_lower(&hashing.finalize().unwrap().file_hashes[0].1)
+    }
+
+    #[test]
+    fn multiple_algs_in_one_pass() {
+        let mut hashing = Hashing::new(
+            &[HashAlg::Md5, HashAlg::Sha256, HashAlg::Md5],
+            &b"abc"[..],
+            2,
+        );
+        for _ in &mut hashing {}
+        let info = hashing.finalize().unwrap();
+
+        assert_eq!(info.file_bytes, 3);
+        assert_eq!(
+            info.file_hashes,
+            vec![
+                (
+                    HashAlg::Md5,
+                    base16::decode("900150983cd24fb0d6963f7d28e17f72").unwrap()
+                ),
+                (
+                    HashAlg::Sha256,
+                    base16::decode(
+                        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                    )
+                    .unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[test_case("BLAKE2b" => Some(HashAlg::Blake2b512); "b2sum tag")]
+    #[test_case("SHA3-256" => Some(HashAlg::Sha3_256); "sha3 tag")]
+    #[test_case("CRC32B" => Some(HashAlg::Crc32); "cksum tag")]
+    fn parse_tag(tag: &str) -> Option<HashAlg> {
+        HashAlg::from_tag(tag)
+    }
+
     #[test_case("asdf-fdsu" => HashParseError::UnknownAlg("asdf".into()); "bad algo")]
     #[test_case("sha256-deadbeef" => HashParseError::InvalidLengthForAlg{ alg: HashAlg::Sha256, expected_bytes: 32, actual_bytes: 4}; "bad length")]
     #[test_case("sha256-" => HashParseError::InvalidLengthForAlg { alg: HashAlg::Sha256, expected_bytes: 32, actual_bytes: 0 }; "sri no hash")]
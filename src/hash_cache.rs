@@ -0,0 +1,240 @@
+//! A cache of the hashes of input files, so that burning the same image over
+//! and over doesn't mean hashing it over and over.
+//!
+//! Entries are keyed on the identity of every part of the input, so they are
+//! ignored as soon as any of the files are modified or replaced.
+
+use std::{
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    hash::{Digests, HashAlg},
+    multipart::ImageParts,
+    ui::cli::HashOf,
+    util::user_cache_dir,
+};
+
+/// How many entries to keep. The least recently added ones are dropped first.
+const MAX_ENTRIES: usize = 64;
+
+/// Identifies a specific version of a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileId {
+    path: PathBuf,
+    size: u64,
+    mtime: SystemTime,
+    dev: u64,
+    inode: u64,
+}
+
+impl FileId {
+    pub fn of(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = fs::canonicalize(path)?;
+        let meta = fs::metadata(&path)?;
+        Ok(Self {
+            path,
+            size: meta.len(),
+            mtime: meta.modified()?,
+            dev: meta.dev(),
+            inode: meta.ino(),
+        })
+    }
+
+    /// Identifies every part of the input.
+    pub fn of_parts(input: &ImageParts) -> io::Result<Vec<Self>> {
+        input.parts().iter().map(Self::of).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    files: Vec<FileId>,
+    alg: HashAlg,
+    /// Either [HashOf::Raw] or [HashOf::Compressed].
+    hash_of: HashOf,
+    digest: Vec<u8>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    entries: Vec<Entry>,
+}
+
+impl HashCache {
+    /// Loads the cache from the user's cache directory. If there is no cache
+    /// yet, or it can't be read, this starts an empty one.
+    pub fn load() -> Self {
+        match user_cache_dir() {
+            Some(dir) => Self::load_from(dir.join("caligula").join("hashes.json")),
+            None => Self::default(),
+        }
+    }
+
+    fn load_from(path: PathBuf) -> Self {
+        let entries = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                debug!(?path, "Ignoring unreadable hash cache: {e}");
+                Self::default()
+            }),
+            Err(e) => {
+                debug!(?path, "Could not read hash cache: {e}");
+                Self::default()
+            }
+        }
+        .entries;
+
+        Self {
+            path: Some(path),
+            entries,
+        }
+    }
+
+    /// Returns the cached digests of `files` for all of `algs`, or None if any
+    /// of them are missing.
+    pub fn get_all(
+        &self,
+        files: &[FileId],
+        algs: impl IntoIterator<Item = HashAlg>,
+        hash_of: HashOf,
+    ) -> Option<Digests> {
+        algs.into_iter()
+            .map(|alg| {
+                let entry = self
+                    .entries
+                    .iter()
+                    .find(|e| e.files == files && e.alg == alg && e.hash_of == hash_of)?;
+                Some((alg, entry.digest.clone()))
+            })
+            .collect()
+    }
+
+    pub fn insert(&mut self, files: &[FileId], alg: HashAlg, hash_of: HashOf, digest: Vec<u8>) {
+        self.entries
+            .retain(|e| !(e.files == files && e.alg == alg && e.hash_of == hash_of));
+        self.entries.push(Entry {
+            files: files.to_vec(),
+            alg,
+            hash_of,
+            digest,
+        });
+
+        let excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+        self.entries.drain(..excess);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Write to a temporary file first, so that another caligula running
+        // at the same time never sees half of a cache.
+        let tmp = path.with_extension(format!("json.{}", std::process::id()));
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::SystemTime};
+
+    use rand::{thread_rng, RngCore};
+
+    use crate::{hash::HashAlg, ui::cli::HashOf};
+
+    use super::{FileId, HashCache, MAX_ENTRIES};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("caligula-{name}-{}", thread_rng().next_u64()))
+    }
+
+    #[test]
+    fn saved_entries_are_loaded() {
+        let image = temp_path("image");
+        let cache_path = temp_path("cache");
+        std::fs::write(&image, b"hello").unwrap();
+        let files = vec![FileId::of(&image).unwrap()];
+
+        let mut cache = HashCache::load_from(cache_path.clone());
+        cache.insert(&files, HashAlg::Sha256, HashOf::Raw, vec![1, 2, 3]);
+        cache.insert(&files, HashAlg::Md5, HashOf::Raw, vec![4, 5, 6]);
+        cache.save().unwrap();
+        let loaded = HashCache::load_from(cache_path.clone());
+
+        assert_eq!(
+            loaded.get_all(&files, [HashAlg::Md5, HashAlg::Sha256], HashOf::Raw),
+            Some(vec![
+                (HashAlg::Md5, vec![4, 5, 6]),
+                (HashAlg::Sha256, vec![1, 2, 3])
+            ])
+        );
+        assert_eq!(
+            loaded.get_all(&files, [HashAlg::Sha256, HashAlg::Sha1], HashOf::Raw),
+            None
+        );
+        assert_eq!(
+            loaded.get_all(&files, [HashAlg::Sha256], HashOf::Compressed),
+            None
+        );
+
+        std::fs::remove_file(&image).unwrap();
+        std::fs::remove_file(&cache_path).unwrap();
+    }
+
+    #[test]
+    fn modified_file_misses() {
+        let image = temp_path("image");
+        std::fs::write(&image, b"hello").unwrap();
+        let before = vec![FileId::of(&image).unwrap()];
+
+        let mut cache = HashCache::default();
+        cache.insert(&before, HashAlg::Sha256, HashOf::Raw, vec![1, 2, 3]);
+        std::fs::write(&image, b"hello, world").unwrap();
+        let after = vec![FileId::of(&image).unwrap()];
+
+        assert_eq!(cache.get_all(&after, [HashAlg::Sha256], HashOf::Raw), None);
+
+        std::fs::remove_file(&image).unwrap();
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped() {
+        let files = |i: usize| {
+            vec![FileId {
+                path: format!("/image-{i}.iso").into(),
+                size: 1000,
+                mtime: SystemTime::UNIX_EPOCH,
+                dev: 1,
+                inode: i as u64,
+            }]
+        };
+
+        let mut cache = HashCache::default();
+        for i in 0..MAX_ENTRIES + 1 {
+            cache.insert(&files(i), HashAlg::Sha256, HashOf::Raw, vec![i as u8]);
+        }
+
+        assert_eq!(cache.entries.len(), MAX_ENTRIES);
+        assert_eq!(
+            cache.get_all(&files(0), [HashAlg::Sha256], HashOf::Raw),
+            None
+        );
+        assert_eq!(
+            cache.get_all(&files(1), [HashAlg::Sha256], HashOf::Raw),
+            Some(vec![(HashAlg::Sha256, vec![1])])
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Noticing when disks are plugged in or pulled out, by listening to the
+//! uevents that the kernel broadcasts for every device it adds or removes.
+
+use std::io;
+
+/// Listens for block devices being added, removed or changed. A card reader
+/// getting a card inserted shows up as a change.
+#[cfg(target_os = "linux")]
+pub struct BlockDeviceWatcher {
+    fd: std::os::fd::OwnedFd,
+}
+
+#[cfg(target_os = "linux")]
+impl BlockDeviceWatcher {
+    pub fn new() -> io::Result<Self> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        // Group 1 gets the events straight from the kernel, rather than the
+        // ones rebroadcast by udev, so this works without udev too.
+        const KERNEL_GROUP: u32 = 1;
+
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = KERNEL_GROUP;
+        let result = unsafe {
+            libc::bind(
+                std::os::fd::AsRawFd::as_raw_fd(&fd),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd })
+    }
+
+    /// Returns whether any block devices changed since the last call. This
+    /// never blocks.
+    pub fn poll_changed(&mut self) -> io::Result<bool> {
+        use std::os::fd::AsRawFd;
+
+        let mut changed = false;
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                return match err.kind() {
+                    io::ErrorKind::WouldBlock => Ok(changed),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => Err(err),
+                };
+            }
+            changed |= is_block_device_event(&buf[..n as usize]);
+        }
+    }
+}
+
+/// There's no netlink to listen to, so callers have to poll instead.
+#[cfg(not(target_os = "linux"))]
+pub struct BlockDeviceWatcher {
+    _private: (),
+}
+
+#[cfg(not(target_os = "linux"))]
+impl BlockDeviceWatcher {
+    pub fn new() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "watching for devices is only supported on Linux",
+        ))
+    }
+
+    pub fn poll_changed(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Whether a uevent is about a block device coming, going or changing. The
+/// message is a header like `add@/devices/...`, followed by NUL-separated
+/// `KEY=value` pairs.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn is_block_device_event(msg: &[u8]) -> bool {
+    let mut fields = msg.split(|b| *b == 0);
+    let Some(header) = fields.next() else {
+        return false;
+    };
+    let interesting_action = [&b"add@"[..], b"remove@", b"change@"]
+        .iter()
+        .any(|a| header.starts_with(a));
+
+    interesting_action && fields.any(|f| f == b"SUBSYSTEM=block")
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::is_block_device_event;
+
+    #[test_case(
+        b"add@/devices/pci0000:00/usb2/2-1/host6/target6:0:0/6:0:0:0/block/sdb\0ACTION=add\0DEVPATH=/devices/pci0000:00/usb2/2-1/host6/target6:0:0/6:0:0:0/block/sdb\0SUBSYSTEM=block\0DEVNAME=sdb\0DEVTYPE=disk\0SEQNUM=4321\0"
+        => true; "disk added"
+    )]
+    #[test_case(
+        b"remove@/devices/virtual/block/loop3\0ACTION=remove\0SUBSYSTEM=block\0DEVNAME=loop3\0"
+        => true; "disk removed"
+    )]
+    #[test_case(
+        b"add@/devices/pci0000:00/usb2/2-1\0ACTION=add\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0"
+        => false; "usb device added"
+    )]
+    #[test_case(
+        b"bind@/devices/pci0000:00/usb2/2-1/2-1:1.0\0ACTION=bind\0SUBSYSTEM=block\0"
+        => false; "other action"
+    )]
+    #[test_case(b"" => false; "empty")]
+    fn block_device_event(msg: &[u8]) -> bool {
+        is_block_device_event(msg)
+    }
+}
@@ -0,0 +1,278 @@
+//! Working out how much space an image needs on the disk before writing it,
+//! so that an image that doesn't fit is caught before the disk is touched.
+
+use std::{
+    fmt::Display,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+};
+
+use bytesize::ByteSize;
+
+use crate::{
+    compression::{decompress, CompressionFormat},
+    multipart::ImageParts,
+};
+
+/// How big the image is once decompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageSize {
+    Exact(u64),
+    /// What the compressed file claims, which may be wrong. For example,
+    /// gzip only stores the size modulo 4 GiB.
+    Estimated(u64),
+    Unknown,
+}
+
+impl Display for ImageSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageSize::Exact(n) => write!(f, "{}", ByteSize::b(*n)),
+            ImageSize::Estimated(n) => write!(f, "about {}", ByteSize::b(*n)),
+            ImageSize::Unknown => write!(f, "[unknown size]"),
+        }
+    }
+}
+
+impl ImageSize {
+    /// Works out the decompressed size of the input without decompressing
+    /// it, from whatever the compression format records about it.
+    pub fn of(input: &ImageParts, cf: CompressionFormat) -> io::Result<Self> {
+        Self::of_reader(input.open()?, cf)
+    }
+
+    fn of_reader(mut r: impl Read + Seek, cf: CompressionFormat) -> io::Result<Self> {
+        let len = r.seek(SeekFrom::End(0))?;
+        match cf {
+            CompressionFormat::Identity => Ok(Self::Exact(len)),
+            CompressionFormat::Gz => gzip_size(r, len),
+            CompressionFormat::Xz => xz_size(r, len),
+            CompressionFormat::Lz4 => lz4_size(r),
+            CompressionFormat::Bz2 => Ok(Self::Unknown),
+        }
+    }
+}
+
+/// The last 4 bytes of a gzip member are its size modulo 2^32. That's only
+/// the last member, if there's more than one, and images are often bigger
+/// than 4 GiB, so the smallest size that could have compressed down to the
+/// compressed file is our best guess.
+fn gzip_size(mut r: impl Read + Seek, len: u64) -> io::Result<ImageSize> {
+    if len < 18 {
+        return Ok(ImageSize::Unknown);
+    }
+    let mut isize = [0u8; 4];
+    r.seek(SeekFrom::End(-4))?;
+    r.read_exact(&mut isize)?;
+
+    // Incompressible data comes out slightly bigger than it went in, because
+    // of the headers and the framing of each block.
+    let min_size = len.saturating_sub(len / 1000 + 1024);
+    let mut size = u32::from_le_bytes(isize) as u64;
+    while size < min_size {
+        size += 1 << 32;
+    }
+    Ok(ImageSize::Estimated(size))
+}
+
+/// xz files end with an index of every block in the stream, including their
+/// uncompressed sizes. If the index accounts for the whole file, the size is
+/// exact. Otherwise, there are more streams before it, and the size is at
+/// least this.
+fn xz_size(mut r: impl Read + Seek, len: u64) -> io::Result<ImageSize> {
+    const HEADER_SIZE: u64 = 12;
+    const FOOTER_SIZE: u64 = 12;
+
+    // Streams may be followed by padding, in multiples of 4 bytes.
+    let mut end = len;
+    let mut word = [0u8; 4];
+    while end >= HEADER_SIZE + FOOTER_SIZE {
+        r.seek(SeekFrom::Start(end - 4))?;
+        r.read_exact(&mut word)?;
+        if word != [0; 4] {
+            break;
+        }
+        end -= 4;
+    }
+    if end < HEADER_SIZE + FOOTER_SIZE {
+        return Ok(ImageSize::Unknown);
+    }
+
+    let mut footer = [0u8; FOOTER_SIZE as usize];
+    r.seek(SeekFrom::Start(end - FOOTER_SIZE))?;
+    r.read_exact(&mut footer)?;
+    if &footer[10..] != b"YZ" {
+        return Ok(ImageSize::Unknown);
+    }
+    let backward_size = u32::from_le_bytes(footer[4..8].try_into().unwrap()) as u64;
+    let index_size = (backward_size + 1) * 4;
+    let Some(index_start) = (end - FOOTER_SIZE).checked_sub(index_size) else {
+        return Ok(ImageSize::Unknown);
+    };
+
+    let mut index = vec![0u8; index_size as usize];
+    r.seek(SeekFrom::Start(index_start))?;
+    r.read_exact(&mut index)?;
+    let Some((blocks_size, uncompressed)) = parse_xz_index(&index) else {
+        return Ok(ImageSize::Unknown);
+    };
+
+    let stream_start = index_start.checked_sub(blocks_size + HEADER_SIZE);
+    Ok(match stream_start {
+        Some(0) => ImageSize::Exact(uncompressed),
+        _ => ImageSize::Estimated(uncompressed),
+    })
+}
+
+/// Returns the total size of the blocks in the stream, and of the data in
+/// them once decompressed.
+fn parse_xz_index(index: &[u8]) -> Option<(u64, u64)> {
+    fn varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+        let mut value = 0u64;
+        for i in 0..9 {
+            let byte = *data.get(*pos)?;
+            *pos += 1;
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    if index.first() != Some(&0) {
+        return None;
+    }
+    let mut pos = 1;
+    let records = varint(index, &mut pos)?;
+    let mut blocks_size = 0u64;
+    let mut uncompressed = 0u64;
+    for _ in 0..records {
+        let unpadded = varint(index, &mut pos)?;
+        blocks_size = blocks_size.checked_add(unpadded.div_ceil(4) * 4)?;
+        uncompressed = uncompressed.checked_add(varint(index, &mut pos)?)?;
+    }
+    Some((blocks_size, uncompressed))
+}
+
+/// lz4 frames may record their content size. There may be more frames after
+/// the first, so it's only an estimate.
+fn lz4_size(mut r: impl Read + Seek) -> io::Result<ImageSize> {
+    const MAGIC: u32 = 0x184D2204;
+    const CONTENT_SIZE_FLAG: u8 = 1 << 3;
+
+    let mut header = [0u8; 14];
+    r.seek(SeekFrom::Start(0))?;
+    if r.read_exact(&mut header).is_err()
+        || u32::from_le_bytes(header[..4].try_into().unwrap()) != MAGIC
+        || header[4] & CONTENT_SIZE_FLAG == 0
+    {
+        return Ok(ImageSize::Unknown);
+    }
+    Ok(ImageSize::Estimated(u64::from_le_bytes(
+        header[6..14].try_into().unwrap(),
+    )))
+}
+
+/// If the image has a GPT partition table, returns how big the disk it was
+/// made for is, going by where it puts the backup header. Anything smaller
+/// cuts off the backup header.
+pub fn gpt_disk_size(input: &ImageParts, cf: CompressionFormat) -> anyhow::Result<Option<u64>> {
+    let r = decompress(cf, BufReader::new(input.open()?))?;
+    Ok(gpt_disk_size_of(r)?)
+}
+
+fn gpt_disk_size_of(r: impl Read) -> io::Result<Option<u64>> {
+    // The header is in the second logical block, which depends on the sector
+    // size that the image was made for.
+    const SECTOR_SIZES: [u64; 2] = [512, 4096];
+    const HEADER_SIZE: usize = 92;
+
+    let mut start = vec![];
+    r.take(4096 + HEADER_SIZE as u64).read_to_end(&mut start)?;
+
+    for sector_size in SECTOR_SIZES {
+        let Some(header) = start.get(sector_size as usize..sector_size as usize + HEADER_SIZE)
+        else {
+            continue;
+        };
+        if &header[..8] != b"EFI PART" {
+            continue;
+        }
+        let backup_lba = u64::from_le_bytes(header[32..40].try_into().unwrap());
+        return Ok(backup_lba
+            .checked_add(1)
+            .and_then(|blocks| blocks.checked_mul(sector_size)));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use test_case::test_case;
+
+    use crate::compression::CompressionFormat;
+
+    use super::{gpt_disk_size_of, ImageSize};
+
+    fn compress(cf: CompressionFormat, data: &[u8]) -> Vec<u8> {
+        match cf {
+            CompressionFormat::Gz => {
+                let mut w = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+                w.write_all(data).unwrap();
+                w.finish().unwrap()
+            }
+            CompressionFormat::Xz => {
+                let mut w = xz2::write::XzEncoder::new(vec![], 1);
+                w.write_all(data).unwrap();
+                w.finish().unwrap()
+            }
+            CompressionFormat::Lz4 => {
+                let info = lz4_flex::frame::FrameInfo::new().content_size(Some(data.len() as u64));
+                let mut w = lz4_flex::frame::FrameEncoder::with_frame_info(info, vec![]);
+                w.write_all(data).unwrap();
+                w.finish().unwrap()
+            }
+            _ => data.to_vec(),
+        }
+    }
+
+    #[test_case(CompressionFormat::Identity => ImageSize::Exact(100_000); "identity")]
+    #[test_case(CompressionFormat::Gz => ImageSize::Estimated(100_000); "gz")]
+    #[test_case(CompressionFormat::Xz => ImageSize::Exact(100_000); "xz")]
+    #[test_case(CompressionFormat::Lz4 => ImageSize::Estimated(100_000); "lz4")]
+    #[test_case(CompressionFormat::Bz2 => ImageSize::Unknown; "bz2")]
+    fn image_size(cf: CompressionFormat) -> ImageSize {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 31 % 251) as u8).collect();
+
+        ImageSize::of_reader(Cursor::new(compress(cf, &data)), cf).unwrap()
+    }
+
+    #[test]
+    fn concatenated_xz_is_estimated() {
+        let data = vec![7u8; 10_000];
+        let mut xz = compress(CompressionFormat::Xz, &data);
+        xz.extend(compress(CompressionFormat::Xz, &data));
+
+        let size = ImageSize::of_reader(Cursor::new(xz), CompressionFormat::Xz).unwrap();
+
+        assert_eq!(size, ImageSize::Estimated(10_000));
+    }
+
+    #[test_case(512 => Some(1000 * 512); "512 byte sectors")]
+    #[test_case(4096 => Some(1000 * 4096); "4k sectors")]
+    fn gpt_disk_size(sector_size: usize) -> Option<u64> {
+        let mut image = vec![0u8; 8192];
+        image[sector_size..sector_size + 8].copy_from_slice(b"EFI PART");
+        image[sector_size + 32..sector_size + 40].copy_from_slice(&999u64.to_le_bytes());
+
+        gpt_disk_size_of(&image[..]).unwrap()
+    }
+
+    #[test]
+    fn no_gpt() {
+        assert_eq!(gpt_disk_size_of(&[0u8; 8192][..]).unwrap(), None);
+        assert_eq!(gpt_disk_size_of(&[0u8; 100][..]).unwrap(), None);
+    }
+}
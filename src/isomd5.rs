@@ -0,0 +1,254 @@
+//! Checksums implanted into ISO images by `implantisomd5`, and checked by
+//! `checkisomd5`. Fedora, RHEL and their derivatives ship ISOs with these.
+//!
+//! The checksum is stored as text in the application use area of the primary
+//! volume descriptor. It is an MD5 of the start of the image (all but a few
+//! sectors at the end) with that application use area blanked out, since the
+//! checksum can't include itself.
+
+use std::io::{self, Read};
+
+use digest::Update;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use valuable::Valuable;
+
+use crate::hash::{HashAlg, Hasher};
+
+const SECTOR_SIZE: u64 = 2048;
+
+/// Volume descriptors start after the 16-sector system area.
+const FIRST_DESCRIPTOR_SECTOR: u64 = 16;
+
+/// How many volume descriptors to look through for the primary one.
+const MAX_DESCRIPTORS: u64 = 16;
+
+/// Where the application use area is in the primary volume descriptor.
+const APPDATA_OFFSET: u64 = 883;
+const APPDATA_SIZE: u64 = 512;
+
+/// A checksum implanted into an ISO.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub struct ImplantedMd5 {
+    pub md5: Vec<u8>,
+    /// How many bytes from the start of the image the checksum covers.
+    pub len: u64,
+    /// Where the application use area starts in the image.
+    appdata_start: u64,
+}
+
+impl ImplantedMd5 {
+    /// Reads the implanted checksum from the start of an image, if it has one.
+    pub fn read(mut r: impl Read) -> io::Result<Option<Self>> {
+        let mut sector = vec![0u8; SECTOR_SIZE as usize];
+        for _ in 0..FIRST_DESCRIPTOR_SECTOR {
+            if !read_sector(&mut r, &mut sector)? {
+                return Ok(None);
+            }
+        }
+
+        for i in 0..MAX_DESCRIPTORS {
+            if !read_sector(&mut r, &mut sector)? || &sector[1..6] != b"CD001" {
+                return Ok(None);
+            }
+            match sector[0] {
+                1 => {
+                    let start = (FIRST_DESCRIPTOR_SECTOR + i) * SECTOR_SIZE;
+                    return Ok(Self::parse_pvd(&sector, start));
+                }
+                // The volume descriptor set terminator.
+                255 => return Ok(None),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Parses the primary volume descriptor found at `pvd_start`.
+    fn parse_pvd(pvd: &[u8], pvd_start: u64) -> Option<Self> {
+        let volume_sectors = u32::from_le_bytes(pvd[80..84].try_into().unwrap()) as u64;
+        let appdata = &pvd[APPDATA_OFFSET as usize..(APPDATA_OFFSET + APPDATA_SIZE) as usize];
+        let appdata = appdata.split(|b| *b == 0).next().unwrap_or_default();
+        let appdata = std::str::from_utf8(appdata).ok()?;
+
+        let mut md5 = None;
+        let mut skip_sectors = None;
+        for field in appdata.split(';') {
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            match key.trim() {
+                "ISO MD5SUM" => md5 = base16::decode(value.trim()).ok(),
+                "SKIPSECTORS" => skip_sectors = value.trim().parse::<u64>().ok(),
+                _ => {}
+            }
+        }
+
+        let md5 = md5.filter(|m| m.len() == HashAlg::Md5.digest_bytes())?;
+        let appdata_start = pvd_start + APPDATA_OFFSET;
+        let len = volume_sectors
+            .checked_sub(skip_sectors?)?
+            .checked_mul(SECTOR_SIZE)
+            .filter(|len| *len >= appdata_start + APPDATA_SIZE);
+        if len.is_none() {
+            debug!(
+                volume_sectors,
+                ?skip_sectors,
+                "Implanted MD5 has a bad length"
+            );
+        }
+
+        Some(Self {
+            md5,
+            len: len?,
+            appdata_start,
+        })
+    }
+}
+
+/// Fills `buf` with the next sector. Returns false if the image ended first.
+fn read_sector(mut r: impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match r.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Computes the implanted checksum of an image as it's read from the start.
+/// Anything after the part covered by the checksum is ignored.
+#[derive(Clone)]
+pub struct ImplantedMd5Hasher {
+    hasher: Hasher,
+    offset: u64,
+    len: u64,
+    appdata_start: u64,
+}
+
+impl ImplantedMd5Hasher {
+    pub fn new(implanted: &ImplantedMd5) -> Self {
+        Self {
+            hasher: Hasher::new(HashAlg::Md5),
+            offset: 0,
+            len: implanted.len,
+            appdata_start: implanted.appdata_start,
+        }
+    }
+
+    /// Returns the checksum, or None if the image was too short.
+    pub fn finalize(self) -> Option<Vec<u8>> {
+        (self.offset == self.len).then(|| self.hasher.finalize())
+    }
+}
+
+impl Update for ImplantedMd5Hasher {
+    fn update(&mut self, data: &[u8]) {
+        let take = (self.len - self.offset).min(data.len() as u64);
+        let data = &data[..take as usize];
+        let end = self.offset + take;
+
+        // The part of the application use area in this chunk, if any.
+        let blank_start = self.appdata_start.max(self.offset);
+        let blank_end = (self.appdata_start + APPDATA_SIZE).min(end);
+        if blank_start < blank_end {
+            let mut data = data.to_vec();
+            data[(blank_start - self.offset) as usize..(blank_end - self.offset) as usize]
+                .fill(b' ');
+            self.hasher.update(&data);
+        } else {
+            self.hasher.update(data);
+        }
+
+        self.offset = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use md5::{Digest, Md5};
+    use test_case::test_case;
+
+    use super::{ImplantedMd5, ImplantedMd5Hasher, APPDATA_OFFSET, APPDATA_SIZE, SECTOR_SIZE};
+    use digest::Update;
+
+    const SECTORS: u64 = 40;
+    const SKIP_SECTORS: u64 = 15;
+
+    /// Makes a tiny ISO with a checksum implanted the same way as
+    /// `implantisomd5` does.
+    fn make_iso() -> Vec<u8> {
+        let mut iso: Vec<u8> = (0..SECTORS * SECTOR_SIZE)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let pvd = 16 * SECTOR_SIZE as usize;
+        iso[pvd] = 1;
+        iso[pvd + 1..pvd + 6].copy_from_slice(b"CD001");
+        iso[pvd + 80..pvd + 84].copy_from_slice(&(SECTORS as u32).to_le_bytes());
+        let terminator = 17 * SECTOR_SIZE as usize;
+        iso[terminator] = 255;
+        iso[terminator + 1..terminator + 6].copy_from_slice(b"CD001");
+
+        let appdata = pvd + APPDATA_OFFSET as usize..pvd + (APPDATA_OFFSET + APPDATA_SIZE) as usize;
+        iso[appdata.clone()].fill(b' ');
+        let md5 = Md5::digest(&iso[..((SECTORS - SKIP_SECTORS) * SECTOR_SIZE) as usize]);
+
+        let text = format!(
+            "ISO MD5SUM = {};SKIPSECTORS = {SKIP_SECTORS};RHLISOSTATUS=1;\
+             THIS IS NOT THE SAME AS RUNNING MD5SUM ON THIS ISO!!",
+            base16::encode_lower(&md5)
+        );
+        iso[appdata][..text.len()].copy_from_slice(text.as_bytes());
+        iso
+    }
+
+    #[test]
+    fn reads_implanted_md5() {
+        let iso = make_iso();
+
+        let implanted = ImplantedMd5::read(&iso[..]).unwrap().unwrap();
+
+        assert_eq!(implanted.len, (SECTORS - SKIP_SECTORS) * SECTOR_SIZE);
+        assert_eq!(implanted.appdata_start, 16 * SECTOR_SIZE + APPDATA_OFFSET);
+    }
+
+    #[test]
+    fn no_implanted_md5() {
+        let mut iso = make_iso();
+        let pvd = (16 * SECTOR_SIZE + APPDATA_OFFSET) as usize;
+        iso[pvd..pvd + APPDATA_SIZE as usize].fill(0);
+
+        assert_eq!(ImplantedMd5::read(&iso[..]).unwrap(), None);
+        assert_eq!(ImplantedMd5::read(&[0u8; 100][..]).unwrap(), None);
+    }
+
+    #[test_case(usize::MAX, None => true; "in one chunk")]
+    #[test_case(1000, None => true; "in small chunks")]
+    #[test_case(4096, Some(50) => false; "corrupted")]
+    #[test_case(4096, Some(16 * 2048 + 900) => true; "appdata is ignored")]
+    #[test_case(4096, Some(39 * 2048) => true; "skipped sectors are ignored")]
+    fn checks_implanted_md5(chunk_size: usize, corrupt_at: Option<usize>) -> bool {
+        let mut iso = make_iso();
+        let implanted = ImplantedMd5::read(&iso[..]).unwrap().unwrap();
+        if let Some(i) = corrupt_at {
+            iso[i] ^= 1;
+        }
+
+        let mut hasher = ImplantedMd5Hasher::new(&implanted);
+        for chunk in iso.chunks(chunk_size.min(iso.len())) {
+            hasher.update(chunk);
+        }
+
+        hasher.finalize().unwrap() == implanted.md5
+    }
+
+    #[test]
+    fn truncated_image() {
+        let iso = make_iso();
+        let implanted = ImplantedMd5::read(&iso[..]).unwrap().unwrap();
+
+        let mut hasher = ImplantedMd5Hasher::new(&implanted);
+        hasher.update(&iso[..20 * SECTOR_SIZE as usize]);
+
+        assert_eq!(hasher.finalize(), None);
+    }
+}
@@ -1,16 +1,28 @@
 use run_mode::RunMode;
 
+mod allowlist;
 mod byteseries;
+mod checksum_file;
 mod childproc_common;
 mod compression;
 mod device;
 mod escalated_daemon;
 mod escalation;
 mod hash;
+mod hash_cache;
+mod hotplug;
+mod image_size;
 mod ipc_common;
+mod isomd5;
 mod logging;
+mod mounts;
+mod multipart;
 mod native;
+mod partitions;
 mod run_mode;
+mod signature;
+mod system_disks;
+mod target_match;
 mod ui;
 mod util;
 mod writer_process;
@@ -0,0 +1,433 @@
+//! Finding out whether a device, or any of its partitions, is in use as a
+//! mounted filesystem or as swap, and getting it out of use.
+//!
+//! Writing over a mounted filesystem corrupts it, and tends to take down
+//! whatever is using it along with it.
+
+use std::{
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use valuable::Valuable;
+
+use crate::device::{Type, WriteTarget};
+
+/// Something that is keeping a device busy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub enum Mount {
+    Filesystem {
+        device: PathBuf,
+        mount_point: PathBuf,
+    },
+    Swap {
+        device: PathBuf,
+    },
+}
+
+impl Mount {
+    /// Unmounts the filesystem or turns off the swap. This usually needs root.
+    #[cfg(target_os = "linux")]
+    pub fn unmount(&self) -> io::Result<()> {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let path = match self {
+            Mount::Filesystem { mount_point, .. } => mount_point,
+            Mount::Swap { device } => device,
+        };
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let result = unsafe {
+            match self {
+                Mount::Filesystem { .. } => libc::umount2(path.as_ptr(), 0),
+                Mount::Swap { .. } => libc::swapoff(path.as_ptr()),
+            }
+        };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn unmount(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unmounting is only supported on Linux",
+        ))
+    }
+}
+
+impl Display for Mount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mount::Filesystem {
+                device,
+                mount_point,
+            } => write!(
+                f,
+                "{} mounted at {}",
+                device.to_string_lossy(),
+                mount_point.to_string_lossy()
+            ),
+            Mount::Swap { device } => write!(f, "{} used as swap", device.to_string_lossy()),
+        }
+    }
+}
+
+/// A line of `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MountInfo {
+    /// The `major:minor` of the mounted device.
+    dev: String,
+    mount_point: PathBuf,
+    source: PathBuf,
+}
+
+/// Everything mounted on the system, as of when it was loaded.
+#[derive(Debug, Default, Clone)]
+pub struct MountTable {
+    mounts: Vec<MountInfo>,
+    swaps: Vec<PathBuf>,
+}
+
+impl MountTable {
+    #[cfg(target_os = "linux")]
+    pub fn load() -> io::Result<Self> {
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+        // There is no /proc/swaps if the kernel was built without swap.
+        let swaps = std::fs::read_to_string("/proc/swaps").unwrap_or_default();
+        Ok(Self::parse(&mountinfo, &swaps))
+    }
+
+    /// There's no equivalent to procfs to look at, so everything looks
+    /// unmounted.
+    #[cfg(not(target_os = "linux"))]
+    pub fn load() -> io::Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn parse(mountinfo: &str, swaps: &str) -> Self {
+        let mounts = mountinfo
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                // The optional fields end with a lone "-", followed by the
+                // filesystem type and the source.
+                let sep = fields.iter().position(|f| *f == "-")?;
+                Some(MountInfo {
+                    dev: fields.get(2)?.to_string(),
+                    mount_point: unescape(fields.get(4)?).into(),
+                    source: unescape(fields.get(sep + 2)?).into(),
+                })
+            })
+            .collect();
+
+        let swaps = swaps
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().next())
+            .map(|f| unescape(f).into())
+            .collect();
+
+        Self { mounts, swaps }
+    }
+
+    /// The source of the filesystem that `path` is on, like `/dev/sda1`.
+    /// `path` must be canonical.
+    pub fn source_of(&self, path: &Path) -> Option<&Path> {
+        // Later mounts hide earlier ones on the same mount point.
+        self.mounts
+            .iter()
+            .filter(|m| path.starts_with(&m.mount_point))
+            .fold(None, |best: Option<&MountInfo>, m| match best {
+                Some(b) if b.mount_point.as_os_str().len() > m.mount_point.as_os_str().len() => {
+                    Some(b)
+                }
+                _ => Some(m),
+            })
+            .map(|m| m.source.as_path())
+    }
+
+    /// Everything in use as swap, whether devices or files.
+    pub fn swaps(&self) -> &[PathBuf] {
+        &self.swaps
+    }
+
+    /// Finds everything using the target or any of its partitions. Mounts are
+    /// returned in the order they should be unmounted in.
+    pub fn mounts_of(&self, target: &WriteTarget) -> io::Result<Vec<Mount>> {
+        if target.target_type == Type::File {
+            return Ok(vec![]);
+        }
+        Ok(self.mounts_of_devices(&block_devices_of(&target.name)?))
+    }
+
+    /// Finds everything using the block device at `devnode` or any of its
+    /// partitions.
+    pub fn mounts_of_devnode(&self, devnode: &Path) -> io::Result<Vec<Mount>> {
+        let devnode = devnode.canonicalize()?;
+        let name = devnode.file_name().unwrap_or_default().to_string_lossy();
+        Ok(self.mounts_of_devices(&block_devices_of(&name)?))
+    }
+
+    /// Finds everything using any of the given `(devnode, major:minor)` pairs.
+    pub fn mounts_of_devices(&self, devices: &[(PathBuf, String)]) -> Vec<Mount> {
+        // Bind mounts and btrfs subvolumes mean there can be more than one
+        // mount per device. Unmounting in reverse order means that anything
+        // mounted inside of another mount goes first.
+        let filesystems = self.mounts.iter().rev().filter_map(|m| {
+            let (device, _) = devices
+                .iter()
+                .find(|(devnode, dev)| *dev == m.dev || same_file(devnode, &m.source))?;
+            Some(Mount::Filesystem {
+                device: device.clone(),
+                mount_point: m.mount_point.clone(),
+            })
+        });
+
+        let swaps = self.swaps.iter().filter_map(|s| {
+            let (device, _) = devices.iter().find(|(devnode, _)| same_file(devnode, s))?;
+            Some(Mount::Swap {
+                device: device.clone(),
+            })
+        });
+
+        filesystems.chain(swaps).collect()
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || b.canonicalize().is_ok_and(|b| a == b)
+}
+
+/// Undoes the octal escaping of spaces and the like in procfs.
+fn unescape(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|o| u8::from_str_radix(std::str::from_utf8(o).ok()?, 8).ok());
+        match escaped {
+            Some(b) => {
+                out.push(b);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// A device stacked on top of the target or one of its partitions, like a
+/// RAID array, an LVM volume or an open LUKS container. These keep using the
+/// device even when nothing is mounted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub struct Holder {
+    /// The target or one of its partitions, like `sdb1`.
+    pub device: String,
+    /// The device using it, like `md127` or `dm-0`.
+    pub holder: String,
+    /// The device mapper name of the holder, like `vg0-root`, if it has one.
+    pub dm_name: Option<String>,
+}
+
+impl Display for Holder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is in use by {}", self.device, self.holder)?;
+        if let Some(name) = &self.dm_name {
+            write!(f, " ({name})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the devices stacked on top of `devnode` or any of its partitions.
+#[cfg(target_os = "linux")]
+pub fn holders_of(devnode: &Path) -> io::Result<Vec<Holder>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if !std::fs::metadata(devnode)?.file_type().is_block_device() {
+        return Ok(vec![]);
+    }
+    let devnode = devnode.canonicalize()?;
+    let sysnode = PathBuf::from("/sys/class/block").join(devnode.file_name().unwrap_or_default());
+
+    let mut holders = vec![];
+    for node in with_partitions(&sysnode)? {
+        let Ok(entries) = std::fs::read_dir(node.join("holders")) else {
+            continue;
+        };
+        for entry in entries {
+            let holder = entry?.path();
+            let dm_name = std::fs::read_to_string(holder.join("dm/name"))
+                .ok()
+                .map(|n| n.trim().to_owned());
+            holders.push(Holder {
+                device: file_name_string(&node),
+                holder: file_name_string(&holder),
+                dm_name,
+            });
+        }
+    }
+    Ok(holders)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn holders_of(_devnode: &Path) -> io::Result<Vec<Holder>> {
+    Ok(vec![])
+}
+
+/// The sysfs directories of a disk and all of its partitions.
+#[cfg(target_os = "linux")]
+fn with_partitions(sysnode: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut nodes = vec![sysnode.to_owned()];
+    for entry in std::fs::read_dir(sysnode)? {
+        let path = entry?.path();
+        if path.join("partition").exists() {
+            nodes.push(path);
+        }
+    }
+    Ok(nodes)
+}
+
+#[cfg(target_os = "linux")]
+fn file_name_string(p: &Path) -> String {
+    p.file_name().unwrap_or_default().to_string_lossy().into()
+}
+
+/// The named block device and all of its partitions, as
+/// `(devnode, major:minor)`.
+#[cfg(target_os = "linux")]
+fn block_devices_of(name: &str) -> io::Result<Vec<(PathBuf, String)>> {
+    let sysnode = PathBuf::from("/sys/class/block").join(name);
+    with_partitions(&sysnode)?
+        .into_iter()
+        .map(|node| {
+            let dev = std::fs::read_to_string(node.join("dev"))?.trim().to_owned();
+            Ok((
+                PathBuf::from("/dev").join(node.file_name().unwrap_or_default()),
+                dev,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn block_devices_of(_name: &str) -> io::Result<Vec<(PathBuf, String)>> {
+    Ok(vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use test_case::test_case;
+
+    use super::{Holder, Mount, MountTable};
+
+    const MOUNTINFO: &str = "\
+22 1 252:1 / / rw,relatime shared:1 - ext4 /dev/vda1 rw
+25 22 0:21 / /proc rw,nosuid shared:12 - proc proc rw
+40 22 8:17 / /run/media/me/MY\\040STICK rw,nosuid shared:30 - vfat /dev/sdb1 rw
+41 40 8:18 / /run/media/me/MY\\040STICK/nested rw shared:31 - ext4 /dev/sdb2 rw
+42 22 0:45 /@home /home rw shared:32 - btrfs /dev/sdc1 rw
+";
+
+    const SWAPS: &str = "\
+Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority
+/dev/sdb3                               partition\t2097148\t\t0\t\t-2
+/swapfile                               file\t\t1048572\t\t0\t\t-3
+";
+
+    fn devices(names: &[(&str, &str)]) -> Vec<(PathBuf, String)> {
+        names
+            .iter()
+            .map(|(name, dev)| (PathBuf::from("/dev").join(name), dev.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_mounted_partitions_innermost_first() {
+        let table = MountTable::parse(MOUNTINFO, SWAPS);
+
+        let mounts = table.mounts_of_devices(&devices(&[
+            ("sdb", "8:16"),
+            ("sdb1", "8:17"),
+            ("sdb2", "8:18"),
+            ("sdb3", "8:19"),
+        ]));
+
+        assert_eq!(
+            mounts,
+            vec![
+                Mount::Filesystem {
+                    device: "/dev/sdb2".into(),
+                    mount_point: "/run/media/me/MY STICK/nested".into(),
+                },
+                Mount::Filesystem {
+                    device: "/dev/sdb1".into(),
+                    mount_point: "/run/media/me/MY STICK".into(),
+                },
+                Mount::Swap {
+                    device: "/dev/sdb3".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_mounts_with_anonymous_device_numbers_by_source() {
+        let table = MountTable::parse(MOUNTINFO, SWAPS);
+
+        let mounts = table.mounts_of_devices(&devices(&[("sdc", "8:32"), ("sdc1", "8:33")]));
+
+        assert_eq!(
+            mounts,
+            vec![Mount::Filesystem {
+                device: "/dev/sdc1".into(),
+                mount_point: "/home".into(),
+            }]
+        );
+    }
+
+    #[test_case("/run/media/me/MY STICK/nested/file" => Some("/dev/sdb2".into()); "nested mount")]
+    #[test_case("/run/media/me/MY STICK/file" => Some("/dev/sdb1".into()); "mount")]
+    #[test_case("/run/media/me/MY STICKER" => Some("/dev/vda1".into()); "not a prefix")]
+    #[test_case("/home/me" => Some("/dev/sdc1".into()); "btrfs subvolume")]
+    fn source_of(path: &str) -> Option<PathBuf> {
+        let table = MountTable::parse(MOUNTINFO, SWAPS);
+
+        table.source_of(Path::new(path)).map(Path::to_owned)
+    }
+
+    #[test_case(None => "sdb1 is in use by md127"; "raid")]
+    #[test_case(Some("luks-1234") => "sdb1 is in use by dm-0 (luks-1234)"; "device mapper")]
+    fn holder_display(dm_name: Option<&str>) -> String {
+        let holder = Holder {
+            device: "sdb1".into(),
+            holder: if dm_name.is_some() { "dm-0" } else { "md127" }.into(),
+            dm_name: dm_name.map(Into::into),
+        };
+
+        holder.to_string()
+    }
+
+    #[test]
+    fn unmounted_device() {
+        let table = MountTable::parse(MOUNTINFO, SWAPS);
+
+        let mounts = table.mounts_of_devices(&devices(&[("sdd", "8:48"), ("sdd1", "8:49")]));
+
+        assert_eq!(mounts, vec![]);
+    }
+}
@@ -0,0 +1,366 @@
+//! Support for images that have been split into multiple files, like
+//! `image.iso.000`, `image.iso.001`, ... or `image.iso.partaa`, `image.iso.partab`, ...
+//!
+//! This usually happens when images are shared on FAT32 media or through channels
+//! with upload size limits. The parts are transparently concatenated before any
+//! decompression happens.
+
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use valuable::Valuable;
+
+/// The list of files that, when concatenated in order, make up the input image.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub struct ImageParts {
+    parts: Vec<PathBuf>,
+}
+
+impl ImageParts {
+    /// Use the given parts in the given order, without any detection.
+    pub fn explicit(parts: Vec<PathBuf>) -> Self {
+        assert!(!parts.is_empty(), "an image must have at least one part");
+        Self { parts }
+    }
+
+    /// Treat the given path as the first part of a split image, and look for
+    /// the parts following it. If the path does not look like the first part
+    /// of a split image, or there are no other parts, the image consists of
+    /// just that one file.
+    pub fn detect(first: impl AsRef<Path>) -> Self {
+        let first = first.as_ref();
+        let mut parts = vec![first.to_owned()];
+
+        if is_first_part(first) {
+            while let Some(next) = next_part_path(parts.last().unwrap()) {
+                if !next.is_file() {
+                    break;
+                }
+                parts.push(next);
+            }
+        }
+
+        Self { parts }
+    }
+
+    pub fn parts(&self) -> &[PathBuf] {
+        &self.parts
+    }
+
+    pub fn first(&self) -> &Path {
+        &self.parts[0]
+    }
+
+    pub fn is_split(&self) -> bool {
+        self.parts.len() > 1
+    }
+
+    /// The path the image would have had if it were never split. For example,
+    /// `image.iso.xz.001` becomes `image.iso.xz`. This is what should be used
+    /// for detecting the compression format.
+    pub fn joined_path(&self) -> PathBuf {
+        if !self.is_split() {
+            return self.first().to_owned();
+        }
+
+        match self
+            .first()
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(split_part_suffix)
+        {
+            Some((stem, _)) => self.first().with_file_name(stem),
+            None => self.first().to_owned(),
+        }
+    }
+
+    /// Open all parts for reading as a single stream.
+    pub fn open(&self) -> io::Result<MultiPartRead<File>> {
+        let files = self
+            .parts
+            .iter()
+            .map(File::open)
+            .collect::<io::Result<Vec<_>>>()?;
+        MultiPartRead::new(files)
+    }
+
+    /// The sum of the sizes of all parts.
+    pub fn total_size(&self) -> io::Result<u64> {
+        let mut total = 0;
+        for p in &self.parts {
+            total += p.metadata()?.len();
+        }
+        Ok(total)
+    }
+}
+
+impl Display for ImageParts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.first().to_string_lossy())?;
+        if self.is_split() {
+            write!(f, " (+{} more parts)", self.parts.len() - 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// The kind of numbering used for a part suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PartSuffix<'a> {
+    /// `.000`, `.001`, ...
+    Numeric(&'a str),
+    /// `.partaa`, `.partab`, ...
+    Alpha(&'a str),
+}
+
+/// Splits a file name into its stem and part suffix, if it has one.
+fn split_part_suffix(name: &str) -> Option<(&str, PartSuffix<'_>)> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    if stem.is_empty() {
+        return None;
+    }
+
+    if ext.len() >= 2 && ext.bytes().all(|b| b.is_ascii_digit()) {
+        return Some((stem, PartSuffix::Numeric(ext)));
+    }
+
+    let letters = ext.strip_prefix("part")?;
+    if letters.len() >= 2 && letters.bytes().all(|b| b.is_ascii_lowercase()) {
+        return Some((stem, PartSuffix::Alpha(letters)));
+    }
+
+    None
+}
+
+fn is_first_part(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+
+    match split_part_suffix(name) {
+        Some((_, PartSuffix::Numeric(n))) => {
+            n.bytes().rev().skip(1).all(|b| b == b'0')
+                && matches!(n.as_bytes().last(), Some(b'0' | b'1'))
+        }
+        Some((_, PartSuffix::Alpha(a))) => a.bytes().all(|b| b == b'a'),
+        None => false,
+    }
+}
+
+/// Returns the file name of the part after this one, or None if the name has
+/// no part suffix or the suffix cannot be incremented.
+fn next_part_name(name: &str) -> Option<String> {
+    let (stem, suffix) = split_part_suffix(name)?;
+
+    match suffix {
+        PartSuffix::Numeric(n) => {
+            let width = n.len();
+            let next = n.parse::<u64>().ok()? + 1;
+            let next = format!("{next:0width$}");
+            if next.len() > width {
+                return None;
+            }
+            Some(format!("{stem}.{next}"))
+        }
+        PartSuffix::Alpha(a) => {
+            let mut letters = a.as_bytes().to_vec();
+            for l in letters.iter_mut().rev() {
+                if *l == b'z' {
+                    *l = b'a';
+                } else {
+                    *l += 1;
+                    let letters = String::from_utf8(letters).unwrap();
+                    return Some(format!("{stem}.part{letters}"));
+                }
+            }
+            None
+        }
+    }
+}
+
+fn next_part_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    Some(path.with_file_name(next_part_name(name)?))
+}
+
+/// A reader over several seekable readers, behaving as if they were concatenated.
+pub struct MultiPartRead<R> {
+    parts: Vec<R>,
+    /// Offset of the start of each part in the concatenated stream.
+    offsets: Vec<u64>,
+    len: u64,
+    /// Current position in the concatenated stream.
+    pos: u64,
+    /// Index of the part that `pos` lies in.
+    current: usize,
+}
+
+impl<R> MultiPartRead<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(mut parts: Vec<R>) -> io::Result<Self> {
+        let mut offsets = Vec::with_capacity(parts.len());
+        let mut len = 0;
+        for p in parts.iter_mut() {
+            offsets.push(len);
+            len += p.seek(SeekFrom::End(0))?;
+            p.seek(SeekFrom::Start(0))?;
+        }
+
+        Ok(Self {
+            parts,
+            offsets,
+            len,
+            pos: 0,
+            current: 0,
+        })
+    }
+
+    /// The total length of all parts.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn part_end(&self, i: usize) -> u64 {
+        self.offsets.get(i + 1).copied().unwrap_or(self.len)
+    }
+}
+
+impl<R> Read for MultiPartRead<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current < self.parts.len() {
+            if self.pos < self.part_end(self.current) {
+                let read = self.parts[self.current].read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    self.pos += read as u64;
+                    return Ok(read);
+                }
+                // The part ended earlier than it did when we opened it.
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "input part shrank while reading",
+                ));
+            }
+
+            self.current += 1;
+            if let Some(p) = self.parts.get_mut(self.current) {
+                p.seek(SeekFrom::Start(0))?;
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl<R> Seek for MultiPartRead<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        // Find the last part starting at or before the target. Empty parts are
+        // skipped over naturally by read().
+        let current = self.offsets.partition_point(|&o| o <= target).max(1) - 1;
+        if let Some(p) = self.parts.get_mut(current) {
+            p.seek(SeekFrom::Start(target - self.offsets[current]))?;
+        }
+
+        self.current = current;
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    use test_case::test_case;
+
+    use super::{is_first_part, next_part_name, MultiPartRead};
+
+    #[test_case("image.iso.000" => Some("image.iso.001".into()); "numeric from zero")]
+    #[test_case("image.iso.009" => Some("image.iso.010".into()); "numeric carry")]
+    #[test_case("image.iso.99" => None; "numeric overflow")]
+    #[test_case("image.iso.partaa" => Some("image.iso.partab".into()); "alpha")]
+    #[test_case("image.iso.partaz" => Some("image.iso.partba".into()); "alpha carry")]
+    #[test_case("image.iso.partzz" => None; "alpha overflow")]
+    #[test_case("image.iso" => None; "not a part")]
+    #[test_case("image.iso.xz" => None; "compression extension")]
+    #[test_case(".001" => None; "no stem")]
+    fn next_part(name: &str) -> Option<String> {
+        next_part_name(name)
+    }
+
+    #[test_case("image.iso.000" => true; "numeric zero")]
+    #[test_case("image.iso.001" => true; "numeric one")]
+    #[test_case("image.iso.002" => false; "numeric two")]
+    #[test_case("image.iso.010" => false; "numeric ten")]
+    #[test_case("image.iso.partaa" => true; "alpha first")]
+    #[test_case("image.iso.partab" => false; "alpha second")]
+    #[test_case("image.iso" => false; "not a part")]
+    fn first_part(name: &str) -> bool {
+        is_first_part(name.as_ref())
+    }
+
+    fn example() -> MultiPartRead<Cursor<Vec<u8>>> {
+        MultiPartRead::new(vec![
+            Cursor::new(vec![0, 1, 2]),
+            Cursor::new(vec![]),
+            Cursor::new(vec![3, 4]),
+            Cursor::new(vec![5, 6, 7, 8]),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_concatenated() {
+        let mut r = example();
+        let mut out = vec![];
+        r.read_to_end(&mut out).unwrap();
+
+        assert_eq!(r.len(), 9);
+        assert_eq!(out, vec![0, 1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test_case(SeekFrom::Start(0) => (0, vec![0, 1, 2, 3, 4, 5, 6, 7, 8]); "start")]
+    #[test_case(SeekFrom::Start(3) => (3, vec![3, 4, 5, 6, 7, 8]); "part boundary")]
+    #[test_case(SeekFrom::Start(6) => (6, vec![6, 7, 8]); "middle of part")]
+    #[test_case(SeekFrom::End(-1) => (8, vec![8]); "from end")]
+    #[test_case(SeekFrom::End(0) => (9, vec![]); "end")]
+    fn seek_then_read(pos: SeekFrom) -> (u64, Vec<u8>) {
+        let mut r = example();
+        r.read_exact(&mut [0; 4]).unwrap();
+        let p = r.seek(pos).unwrap();
+        let mut out = vec![];
+        r.read_to_end(&mut out).unwrap();
+        (p, out)
+    }
+
+    #[test]
+    fn stream_position_tracks_reads() {
+        let mut r = example();
+        r.read_exact(&mut [0; 5]).unwrap();
+
+        assert_eq!(r.stream_position().unwrap(), 5);
+    }
+}
@@ -0,0 +1,458 @@
+//! Describing what's currently on a disk, so that users can recognize what
+//! they're about to destroy.
+//!
+//! Filesystems are looked up in the udev database where possible, since that
+//! doesn't need permission to read the disk. Otherwise, we look at their
+//! superblocks ourselves, which only works if we can read the disk.
+
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use bytesize::ByteSize;
+
+use crate::mounts::{Mount, MountTable};
+
+/// How much of the start of a filesystem to read to find out what it is.
+/// This reaches the btrfs superblock, which is the furthest in.
+const PROBE_SIZE: usize = 0x10000 + 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// The name of the partition, like `sdb1`. If the disk has a filesystem
+    /// on it without a partition table, this is the disk itself.
+    pub name: String,
+    pub size: Option<u64>,
+    pub filesystem: Option<Filesystem>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Filesystem {
+    pub fs_type: String,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    /// How much of the filesystem is used, if that's cheap to find out.
+    pub used: Option<u64>,
+}
+
+impl Display for Partition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(size) = self.size {
+            write!(f, ": {}", ByteSize::b(size))?;
+        }
+        match &self.filesystem {
+            Some(fs) => write!(f, " {fs}"),
+            None => write!(f, " [unknown filesystem]"),
+        }
+    }
+}
+
+impl Display for Filesystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.fs_type)?;
+        if let Some(label) = &self.label {
+            write!(f, " {label:?}")?;
+        }
+        if let Some(uuid) = &self.uuid {
+            write!(f, " (UUID {uuid})")?;
+        }
+        if let Some(used) = self.used {
+            write!(f, ", {} used", ByteSize::b(used))?;
+        }
+        Ok(())
+    }
+}
+
+impl Filesystem {
+    /// A short name for the filesystem, like its label.
+    pub fn short_name(&self) -> String {
+        match &self.label {
+            Some(label) => format!("{label} ({})", self.fs_type),
+            None => self.fs_type.clone(),
+        }
+    }
+}
+
+/// How much of a partition table to read to find out what it is. The GPT
+/// header is in the second block, which is 4 KiB in on 4Kn disks.
+const PARTITION_TABLE_PROBE_SIZE: usize = 2 * 4096;
+
+/// Returns the type of partition table on the disk, and its partitions. If it
+/// has no partitions, but has a filesystem, that is returned instead.
+///
+/// Unless `read_disk` is set, only udev is asked, since reading the disk
+/// spins it up if it's idle, and can block for a long time on slow media.
+#[cfg(target_os = "linux")]
+pub fn partitions_of(
+    name: &str,
+    mounts: &MountTable,
+    read_disk: bool,
+) -> io::Result<(Option<String>, Vec<Partition>)> {
+    use std::path::PathBuf;
+
+    let sysnode = PathBuf::from("/sys/class/block").join(name);
+    let devnode = PathBuf::from("/dev").join(name);
+
+    let mut parts = vec![];
+    for entry in std::fs::read_dir(&sysnode)? {
+        let path = entry?.path();
+        let Ok(number) = std::fs::read_to_string(path.join("partition")) else {
+            continue;
+        };
+        let number: u32 = number.trim().parse().unwrap_or_default();
+        parts.push((number, path));
+    }
+    parts.sort();
+
+    let partition_table = match parts.is_empty() || !read_disk {
+        true => None,
+        false => read_start(&devnode, PARTITION_TABLE_PROBE_SIZE)
+            .ok()
+            .and_then(|start| partition_table_type(&start)),
+    };
+
+    let partitions = match parts.is_empty() {
+        // A disk without partitions might still have a filesystem, like
+        // a floppy or an ISO written to a USB stick.
+        true => describe(&sysnode, mounts, read_disk)
+            .filter(|p| p.filesystem.is_some())
+            .into_iter()
+            .collect(),
+        false => parts
+            .iter()
+            .filter_map(|(_, path)| describe(path, mounts, read_disk))
+            .collect(),
+    };
+
+    Ok((partition_table, partitions))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn partitions_of(
+    _name: &str,
+    _mounts: &MountTable,
+    _read_disk: bool,
+) -> io::Result<(Option<String>, Vec<Partition>)> {
+    Ok((None, vec![]))
+}
+
+/// Describes the block device at `sysnode`.
+#[cfg(target_os = "linux")]
+fn describe(sysnode: &Path, mounts: &MountTable, read_disk: bool) -> Option<Partition> {
+    let name = sysnode.file_name()?.to_string_lossy().into_owned();
+    let devnode = Path::new("/dev").join(&name);
+    let read = |file: &str| {
+        std::fs::read_to_string(sysnode.join(file))
+            .ok()
+            .map(|s| s.trim().to_owned())
+    };
+    let size = read("size")
+        .and_then(|s| s.parse::<u64>().ok())
+        .map(|s| s * 512);
+    let dev = read("dev")?;
+
+    let mut filesystem = from_udev(&dev).or_else(|| {
+        if !read_disk {
+            return None;
+        }
+        let start = read_start(&devnode, PROBE_SIZE).ok()?;
+        probe(&start)
+    });
+
+    // If it's mounted, the kernel can tell us exactly how much is used.
+    if let Some(fs) = &mut filesystem {
+        let mount_point = mounts
+            .mounts_of_devices(&[(devnode.clone(), dev)])
+            .into_iter()
+            .find_map(|m| match m {
+                Mount::Filesystem { mount_point, .. } => Some(mount_point),
+                Mount::Swap { .. } => None,
+            });
+        if let Some(used) = mount_point.and_then(|p| used_by_mount(&p)) {
+            fs.used = Some(used);
+        }
+    }
+
+    Some(Partition {
+        name,
+        size,
+        filesystem,
+    })
+}
+
+/// Looks up a block device in the udev database, by its `major:minor`.
+#[cfg(target_os = "linux")]
+fn from_udev(dev: &str) -> Option<Filesystem> {
+    let data = std::fs::read_to_string(format!("/run/udev/data/b{dev}")).ok()?;
+    parse_udev(&data)
+}
+
+fn parse_udev(data: &str) -> Option<Filesystem> {
+    let get = |key: &str| {
+        data.lines()
+            .find_map(|l| l.strip_prefix("E:")?.strip_prefix(key)?.strip_prefix('='))
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+    };
+    Some(Filesystem {
+        fs_type: get("ID_FS_TYPE")?,
+        label: get("ID_FS_LABEL"),
+        uuid: get("ID_FS_UUID"),
+        used: None,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn used_by_mount(mount_point: &Path) -> Option<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(mount_point.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some((stat.f_blocks - stat.f_bfree) * stat.f_frsize)
+}
+
+/// Reads up to `len` bytes from the start of a device.
+fn read_start(devnode: &Path, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![];
+    File::open(devnode)?
+        .take(len as u64)
+        .read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn partition_table_type(start: &[u8]) -> Option<String> {
+    // GPT disks also have a protective MBR, so look for GPT first, in the
+    // second block for either size of block.
+    let gpt_at = |block: usize| start.get(block..block + 8) == Some(b"EFI PART");
+    if gpt_at(512) || gpt_at(4096) {
+        Some("gpt".into())
+    } else if start.get(510..512) == Some(&[0x55, 0xaa]) {
+        Some("dos".into())
+    } else {
+        None
+    }
+}
+
+/// Works out what filesystem starts with `data`, from its superblock.
+fn probe(data: &[u8]) -> Option<Filesystem> {
+    let at = |offset: usize, len: usize| data.get(offset..offset + len);
+    let u16_at = |offset| Some(u16::from_le_bytes(at(offset, 2)?.try_into().ok()?));
+    let u32_at = |offset| Some(u32::from_le_bytes(at(offset, 4)?.try_into().ok()?));
+    let u64_at = |offset| Some(u64::from_le_bytes(at(offset, 8)?.try_into().ok()?));
+    let text_at = |offset, len| {
+        let s = String::from_utf8_lossy(at(offset, len)?);
+        let s = s.trim_end_matches(['\0', ' ']);
+        (!s.is_empty()).then(|| s.to_owned())
+    };
+    let fs = |fs_type: &str, label: Option<String>, uuid: Option<String>, used: Option<u64>| {
+        Some(Filesystem {
+            fs_type: fs_type.into(),
+            label,
+            uuid,
+            used,
+        })
+    };
+
+    // ext2/3/4
+    const EXT: usize = 0x400;
+    if u16_at(EXT + 0x38) == Some(0xef53) {
+        let compat = u32_at(EXT + 0x5c)?;
+        let incompat = u32_at(EXT + 0x60)?;
+        let is_64bit = incompat & 0x80 != 0;
+        let hi = |offset| match is_64bit {
+            true => u32_at(offset).map(|n| (n as u64) << 32),
+            false => Some(0),
+        };
+        let blocks = u32_at(EXT + 0x4)? as u64 | hi(EXT + 0x150)?;
+        let free = u32_at(EXT + 0xc)? as u64 | hi(EXT + 0x158)?;
+        let block_size = 1024u64 << u32_at(EXT + 0x18)?.min(16);
+        let fs_type = if incompat & (0x40 | 0x80) != 0 {
+            "ext4"
+        } else if compat & 0x4 != 0 {
+            "ext3"
+        } else {
+            "ext2"
+        };
+        return fs(
+            fs_type,
+            text_at(EXT + 0x78, 16),
+            at(EXT + 0x68, 16).map(format_uuid),
+            blocks.checked_sub(free).map(|used| used * block_size),
+        );
+    }
+
+    // btrfs
+    const BTRFS: usize = 0x10000;
+    if at(BTRFS + 0x40, 8) == Some(b"_BHRfS_M") {
+        return fs(
+            "btrfs",
+            text_at(BTRFS + 0x12b, 256),
+            at(BTRFS + 0x20, 16).map(format_uuid),
+            u64_at(BTRFS + 0x78),
+        );
+    }
+
+    if at(0, 4) == Some(b"XFSB") {
+        return fs("xfs", text_at(108, 12), at(32, 16).map(format_uuid), None);
+    }
+    if at(0, 6) == Some(b"LUKS\xba\xbe") {
+        return fs("crypto_LUKS", None, text_at(168, 40), None);
+    }
+    if at(0, 4) == Some(b"hsqs") {
+        return fs("squashfs", None, None, None);
+    }
+    if at(0x8001, 5) == Some(b"CD001") {
+        return fs("iso9660", text_at(0x8000 + 40, 32), None, None);
+    }
+    if at(4096 - 10, 10) == Some(b"SWAPSPACE2") {
+        return fs(
+            "swap",
+            text_at(0x400 + 28, 16),
+            at(0x400 + 12, 16).map(format_uuid),
+            None,
+        );
+    }
+    if at(3, 8) == Some(b"NTFS    ") {
+        return fs("ntfs", None, u64_at(72).map(|s| format!("{s:016X}")), None);
+    }
+    if at(3, 8) == Some(b"EXFAT   ") {
+        return fs("exfat", None, u32_at(100).map(format_serial), None);
+    }
+    if at(510, 2) == Some(&[0x55, 0xaa]) {
+        // FAT32 keeps its volume ID and label further in than FAT12/16.
+        let (id, label) = if at(82, 5) == Some(b"FAT32") {
+            (67, 71)
+        } else if at(54, 3) == Some(b"FAT") {
+            (39, 43)
+        } else {
+            return None;
+        };
+        return fs(
+            "vfat",
+            text_at(label, 11).filter(|l| l != "NO NAME"),
+            u32_at(id).map(format_serial),
+            None,
+        );
+    }
+
+    None
+}
+
+fn format_uuid(b: &[u8]) -> String {
+    let hex = base16::encode_lower(b);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Formats a FAT or exFAT volume serial number like `1234-ABCD`.
+fn format_serial(serial: u32) -> String {
+    format!("{:04X}-{:04X}", serial >> 16, serial & 0xffff)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::{
+        parse_udev, partition_table_type, probe, Filesystem, PARTITION_TABLE_PROBE_SIZE, PROBE_SIZE,
+    };
+
+    fn ext4_image() -> Vec<u8> {
+        let mut data = vec![0u8; PROBE_SIZE];
+        let sb = 0x400;
+        data[sb + 0x38..sb + 0x3a].copy_from_slice(&0xef53u16.to_le_bytes());
+        data[sb + 0x4..sb + 0x8].copy_from_slice(&1000u32.to_le_bytes());
+        data[sb + 0xc..sb + 0x10].copy_from_slice(&600u32.to_le_bytes());
+        data[sb + 0x18..sb + 0x1c].copy_from_slice(&2u32.to_le_bytes());
+        data[sb + 0x60..sb + 0x64].copy_from_slice(&0x40u32.to_le_bytes());
+        data[sb + 0x68..sb + 0x78].copy_from_slice(&[0x11; 16]);
+        data[sb + 0x78..sb + 0x7e].copy_from_slice(b"backup");
+        data
+    }
+
+    fn fat32_image() -> Vec<u8> {
+        let mut data = vec![0u8; PROBE_SIZE];
+        data[82..87].copy_from_slice(b"FAT32");
+        data[67..71].copy_from_slice(&0x1234abcdu32.to_le_bytes());
+        data[71..82].copy_from_slice(b"MY STICK   ");
+        data[510..512].copy_from_slice(&[0x55, 0xaa]);
+        data
+    }
+
+    fn iso_image() -> Vec<u8> {
+        let mut data = vec![0u8; PROBE_SIZE];
+        data[0x8001..0x8006].copy_from_slice(b"CD001");
+        data[0x8028..0x8048].copy_from_slice(b"Fedora-WS-Live-40               ");
+        data
+    }
+
+    #[test_case(ext4_image() => Some(Filesystem {
+        fs_type: "ext4".into(),
+        label: Some("backup".into()),
+        uuid: Some("11111111-1111-1111-1111-111111111111".into()),
+        used: Some(400 * 4096),
+    }); "ext4")]
+    #[test_case(fat32_image() => Some(Filesystem {
+        fs_type: "vfat".into(),
+        label: Some("MY STICK".into()),
+        uuid: Some("1234-ABCD".into()),
+        used: None,
+    }); "fat32")]
+    #[test_case(iso_image() => Some(Filesystem {
+        fs_type: "iso9660".into(),
+        label: Some("Fedora-WS-Live-40".into()),
+        uuid: None,
+        used: None,
+    }); "iso")]
+    #[test_case(vec![0u8; PROBE_SIZE] => None; "nothing")]
+    #[test_case(vec![] => None; "empty")]
+    fn probes_filesystem(data: Vec<u8>) -> Option<Filesystem> {
+        probe(&data)
+    }
+
+    #[test]
+    fn parses_udev_database() {
+        let data = "S:disk/by-uuid/1234-ABCD\nE:ID_FS_UUID=1234-ABCD\nE:ID_FS_UUID_ENC=1234-ABCD\nE:ID_FS_TYPE=vfat\nE:ID_FS_LABEL=\nE:ID_FS_USAGE=filesystem\n";
+
+        assert_eq!(
+            parse_udev(data),
+            Some(Filesystem {
+                fs_type: "vfat".into(),
+                label: None,
+                uuid: Some("1234-ABCD".into()),
+                used: None,
+            })
+        );
+        assert_eq!(parse_udev("E:ID_PART_TABLE_TYPE=gpt\n"), None);
+    }
+
+    #[test]
+    fn detects_partition_table() {
+        let mut mbr = vec![0u8; 1024];
+        mbr[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let mut gpt = mbr.clone();
+        gpt[512..520].copy_from_slice(b"EFI PART");
+
+        let mut gpt_4kn = vec![0u8; PARTITION_TABLE_PROBE_SIZE];
+        gpt_4kn[510..512].copy_from_slice(&[0x55, 0xaa]);
+        gpt_4kn[4096..4104].copy_from_slice(b"EFI PART");
+
+        assert_eq!(partition_table_type(&mbr), Some("dos".into()));
+        assert_eq!(partition_table_type(&gpt), Some("gpt".into()));
+        assert_eq!(partition_table_type(&gpt_4kn), Some("gpt".into()));
+        assert_eq!(partition_table_type(&[0u8; 1024]), None);
+    }
+}
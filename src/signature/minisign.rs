@@ -0,0 +1,156 @@
+//! Verification of [minisign](https://jedisct1.github.io/minisign/) signatures.
+
+use std::io::Read;
+
+use base64::Engine;
+use minisign_verify::{Error as MinisignError, PublicKey, Signature};
+
+use super::{read_message, SignatureError};
+
+/// Parses a public key, either as the contents of a `minisign.pub` file or as
+/// the bare base64 key that minisign prints.
+pub fn parse_public_key(data: &[u8]) -> Result<PublicKey, SignatureError> {
+    let text = std::str::from_utf8(data)
+        .map_err(|_| SignatureError::Malformed("minisign public key"))?
+        .trim();
+
+    if text.lines().count() == 1 {
+        PublicKey::from_base64(text)
+    } else {
+        PublicKey::decode(text)
+    }
+    .map_err(|e| convert_error(e, "minisign public key"))
+}
+
+/// Verifies a `.minisig` signature over `data`. On success, returns a
+/// description of the key that made the signature, along with the signature's
+/// trusted comment.
+pub fn verify_detached(
+    key: &PublicKey,
+    signature: &[u8],
+    mut data: impl Read,
+) -> Result<String, SignatureError> {
+    let text = std::str::from_utf8(signature)
+        .map_err(|_| SignatureError::Malformed("minisign signature"))?;
+    let sig = Signature::decode(text).map_err(|e| convert_error(e, "minisign signature"))?;
+
+    let result = match key.verify_stream(&sig) {
+        Ok(mut verifier) => {
+            let mut buf = vec![0u8; 512 * 1024];
+            loop {
+                let read = data.read(&mut buf)?;
+                if read == 0 {
+                    break;
+                }
+                verifier.update(&buf[..read]);
+            }
+            verifier.finalize()
+        }
+        // Legacy signatures are made over the whole message, not its hash.
+        Err(MinisignError::UnsupportedLegacyMode) => {
+            let message = read_message(data, "legacy minisign signature")?;
+            key.verify(&message, &sig, true)
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => {}
+        Err(MinisignError::UnexpectedKeyId) => {
+            return Err(SignatureError::NoMatchingKey(
+                signature_key_id(text).unwrap_or_else(|| "unknown".into()),
+            ))
+        }
+        Err(e) => return Err(convert_error(e, "minisign signature")),
+    }
+
+    let signer = key
+        .untrusted_comment()
+        .map(|c| c.trim_start_matches("untrusted comment:").trim())
+        .unwrap_or("the given minisign key");
+    Ok(format!(
+        "{signer} (trusted comment: {})",
+        sig.trusted_comment()
+    ))
+}
+
+/// Extracts the key ID from a signature, formatted the same way minisign does.
+fn signature_key_id(signature: &str) -> Option<String> {
+    let line = signature.lines().nth(1)?;
+    let bin = base64::engine::general_purpose::STANDARD
+        .decode(line.trim())
+        .ok()?;
+    let id: [u8; 8] = bin.get(2..10)?.try_into().ok()?;
+    Some(format!("{:016X}", u64::from_le_bytes(id)))
+}
+
+fn convert_error(e: MinisignError, what: &'static str) -> SignatureError {
+    match e {
+        MinisignError::InvalidSignature => SignatureError::BadSignature,
+        MinisignError::InvalidEncoding => SignatureError::Malformed(what),
+        MinisignError::IoError(e) => SignatureError::IO(e),
+        other => SignatureError::Unsupported(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_public_key, verify_detached};
+    use crate::signature::SignatureError;
+
+    const DATA: &[u8] = include_bytes!("testdata/data.txt");
+    const SUMS: &[u8] = include_bytes!("testdata/SHA256SUMS");
+    const PUBKEY: &[u8] = include_bytes!("testdata/minisign.pub");
+    const DATA_SIG: &[u8] = include_bytes!("testdata/data.txt.minisig");
+    const SUMS_LEGACY_SIG: &[u8] = include_bytes!("testdata/SHA256SUMS.legacy.minisig");
+    const SIGNIFY_PUBKEY: &[u8] = include_bytes!("testdata/signify.pub");
+
+    #[test]
+    fn prehashed_signature() {
+        let key = parse_public_key(PUBKEY).unwrap();
+
+        let signer = verify_detached(&key, DATA_SIG, DATA).unwrap();
+
+        assert_eq!(
+            signer,
+            "minisign public key 8877665544332211 \
+             (trusted comment: timestamp:1700000000\tfile:data.txt\thashed)"
+        );
+    }
+
+    #[test]
+    fn legacy_signature() {
+        let key = parse_public_key(PUBKEY).unwrap();
+
+        verify_detached(&key, SUMS_LEGACY_SIG, SUMS).unwrap();
+    }
+
+    #[test]
+    fn bare_base64_key() {
+        let bare = std::str::from_utf8(PUBKEY).unwrap().lines().nth(1).unwrap();
+        let key = parse_public_key(bare.as_bytes()).unwrap();
+
+        verify_detached(&key, DATA_SIG, DATA).unwrap();
+    }
+
+    #[test]
+    fn tampered_data_is_rejected() {
+        let key = parse_public_key(PUBKEY).unwrap();
+
+        let err = verify_detached(&key, DATA_SIG, SUMS).unwrap_err();
+
+        assert!(matches!(err, SignatureError::BadSignature), "{err:?}");
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let key = parse_public_key(SIGNIFY_PUBKEY).unwrap();
+
+        let err = verify_detached(&key, DATA_SIG, DATA).unwrap_err();
+
+        assert!(
+            matches!(&err, SignatureError::NoMatchingKey(id) if id == "8877665544332211"),
+            "{err:?}"
+        );
+    }
+}
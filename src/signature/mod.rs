@@ -0,0 +1,120 @@
+//! Verification of detached signatures over images and checksum files.
+//!
+//! A matching hash only proves integrity if the hash itself is trusted, so this
+//! lets the user check that the image or checksum file was signed by a key
+//! they trust before we burn anything.
+
+use std::{fmt::Display, io::Read};
+
+use bytesize::ByteSize;
+
+pub mod minisign;
+pub mod openpgp;
+pub mod signify;
+
+/// Schemes that sign the message itself rather than a hash of it need the
+/// whole message in memory. Those are meant for checksum files, not images,
+/// so we refuse to read more than this.
+const MAX_UNHASHED_MESSAGE: ByteSize = ByteSize::mib(256);
+
+/// A signature that was successfully verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedSignature {
+    pub scheme: SignatureScheme,
+    /// The name of the file the signature covers.
+    pub signed_file: String,
+    /// A user-friendly description of the key that made the signature.
+    pub signer: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    OpenPgp,
+    Minisign,
+    Signify,
+}
+
+/// The key(s) the user told us to trust.
+#[derive(Debug, Clone)]
+pub enum TrustedKey {
+    OpenPgp(openpgp::Keyring),
+    Minisign(::minisign_verify::PublicKey),
+    Signify(signify::PublicKey),
+}
+
+impl TrustedKey {
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            TrustedKey::OpenPgp(_) => SignatureScheme::OpenPgp,
+            TrustedKey::Minisign(_) => SignatureScheme::Minisign,
+            TrustedKey::Signify(_) => SignatureScheme::Signify,
+        }
+    }
+
+    /// Verifies a detached signature over `data`. On success, returns a
+    /// description of the key that made the signature.
+    pub fn verify_detached(
+        &self,
+        signature: &[u8],
+        data: impl Read,
+    ) -> Result<String, SignatureError> {
+        match self {
+            TrustedKey::OpenPgp(k) => openpgp::verify_detached(k, signature, data),
+            TrustedKey::Minisign(k) => minisign::verify_detached(k, signature, data),
+            TrustedKey::Signify(k) => signify::verify_detached(k, signature, data),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
+    #[error("Unsupported {0}")]
+    Unsupported(String),
+    #[error("The signature was made by key {0}, which is not one of the trusted keys")]
+    NoMatchingKey(String),
+    #[error("No usable keys found in the keyring")]
+    EmptyKeyring,
+    #[error("No signatures found in the signature file")]
+    NoSignatures,
+    #[error("BAD SIGNATURE! The signed file may have been tampered with.")]
+    BadSignature,
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+impl Display for SignatureScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureScheme::OpenPgp => write!(f, "OpenPGP"),
+            SignatureScheme::Minisign => write!(f, "minisign"),
+            SignatureScheme::Signify => write!(f, "signify"),
+        }
+    }
+}
+
+impl Display for VerifiedSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Good {} signature on {} by {}",
+            self.scheme, self.signed_file, self.signer
+        )
+    }
+}
+
+/// Reads a message that has to be verified as a whole.
+fn read_message(data: impl Read, what: &str) -> Result<Vec<u8>, SignatureError> {
+    let limit = MAX_UNHASHED_MESSAGE.as_u64();
+    let mut message = vec![];
+    data.take(limit + 1).read_to_end(&mut message)?;
+
+    if message.len() as u64 > limit {
+        return Err(SignatureError::Unsupported(format!(
+            "{what} over a file larger than {MAX_UNHASHED_MESSAGE}; \
+             sign a checksum file instead"
+        )));
+    }
+    Ok(message)
+}
@@ -0,0 +1,749 @@
+//! A minimal, verification-only implementation of OpenPGP (RFC 4880) detached
+//! signatures. Only what's needed to check the signatures distros publish is
+//! supported: v4 keys and signatures made with RSA or Ed25519 over SHA-2.
+//!
+//! Subkeys are only used if they carry a valid binding signature from their
+//! primary key, and a valid back-signature from themselves. Expiry and
+//! revocation are not checked; the keyring the user gives us is trusted as-is.
+
+use std::io::Read;
+
+use base64::Engine;
+use digest::Digest;
+use ed25519_dalek::VerifyingKey;
+use rsa::{traits::PublicKeyParts, BigUint, Pkcs1v15Sign, RsaPublicKey};
+
+use super::SignatureError;
+
+const TAG_SIGNATURE: u8 = 2;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_USER_ID: u8 = 13;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+
+const SIG_TYPE_BINARY: u8 = 0x00;
+const SIG_TYPE_TEXT: u8 = 0x01;
+const SIG_TYPE_SUBKEY_BINDING: u8 = 0x18;
+const SIG_TYPE_PRIMARY_KEY_BINDING: u8 = 0x19;
+
+const KEY_FLAG_SIGN: u8 = 0x02;
+
+const ED25519_LEGACY_OID: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01];
+
+/// A set of certificates (primary keys with their subkeys) to verify against.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    certs: Vec<Cert>,
+}
+
+#[derive(Debug, Clone)]
+struct Cert {
+    primary: PublicKey,
+    subkeys: Vec<PublicKey>,
+    user_id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct PublicKey {
+    fingerprint: [u8; 20],
+    material: KeyMaterial,
+    /// The raw packet body, needed for hashing binding signatures.
+    body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+enum KeyMaterial {
+    Rsa(RsaPublicKey),
+    Ed25519(VerifyingKey),
+}
+
+#[derive(Debug, Clone)]
+struct Signature<'a> {
+    sig_type: u8,
+    hash_alg: u8,
+    /// Everything from the version number to the end of the hashed subpackets.
+    hashed: &'a [u8],
+    issuer_key_id: Option<&'a [u8]>,
+    issuer_fingerprint: Option<&'a [u8]>,
+    key_flags: Option<u8>,
+    /// The body of an embedded signature, which for subkey bindings is the
+    /// subkey's back-signature.
+    embedded: Option<&'a [u8]>,
+    hash_prefix: &'a [u8],
+    material: SignatureMaterial,
+}
+
+#[derive(Debug, Clone)]
+enum SignatureMaterial {
+    Rsa(Vec<u8>),
+    Ed25519([u8; 64]),
+}
+
+impl Keyring {
+    /// Parses a keyring, either binary or ASCII-armored. Keys that use
+    /// unsupported algorithms are skipped.
+    pub fn parse(data: &[u8]) -> Result<Self, SignatureError> {
+        let data = dearmor(data)?;
+
+        let mut certs: Vec<Cert> = vec![];
+        // Whether the packets we are looking at belong to the last cert in `certs`.
+        let mut in_cert = false;
+        let mut pending_subkey: Option<PublicKey> = None;
+
+        for packet in parse_packets(&data)? {
+            match packet.tag {
+                TAG_PUBLIC_KEY => {
+                    pending_subkey = None;
+                    in_cert = match PublicKey::parse(packet.body)? {
+                        Some(primary) => {
+                            certs.push(Cert {
+                                primary,
+                                subkeys: vec![],
+                                user_id: None,
+                            });
+                            true
+                        }
+                        None => false,
+                    };
+                }
+                TAG_USER_ID if in_cert => {
+                    let cert = certs.last_mut().unwrap();
+                    if cert.user_id.is_none() {
+                        cert.user_id = Some(String::from_utf8_lossy(packet.body).into_owned());
+                    }
+                }
+                TAG_PUBLIC_SUBKEY if in_cert => {
+                    pending_subkey = PublicKey::parse(packet.body)?;
+                }
+                TAG_SIGNATURE if in_cert && pending_subkey.is_some() => {
+                    let Some(sig) = Signature::parse(packet.body)? else {
+                        continue;
+                    };
+                    let cert = certs.last_mut().unwrap();
+                    let subkey = pending_subkey.as_ref().unwrap();
+
+                    if sig.sig_type == SIG_TYPE_SUBKEY_BINDING
+                        && sig.key_flags.is_none_or(|f| f & KEY_FLAG_SIGN != 0)
+                        && verify_subkey_binding(&cert.primary, subkey, &sig).is_ok()
+                        && verify_back_signature(&cert.primary, subkey, &sig).is_ok()
+                    {
+                        cert.subkeys.push(pending_subkey.take().unwrap());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if certs.is_empty() {
+            return Err(SignatureError::EmptyKeyring);
+        }
+        Ok(Self { certs })
+    }
+
+    /// Returns all keys that could have made the given signature, along with
+    /// their certificate.
+    fn candidates<'a>(
+        &'a self,
+        sig: &'a Signature,
+    ) -> impl Iterator<Item = (&'a Cert, &'a PublicKey)> + 'a {
+        self.certs
+            .iter()
+            .flat_map(|c| {
+                [&c.primary]
+                    .into_iter()
+                    .chain(&c.subkeys)
+                    .map(move |k| (c, k))
+            })
+            .filter(|(_, k)| match (sig.issuer_fingerprint, sig.issuer_key_id) {
+                (Some(fpr), _) => fpr == k.fingerprint,
+                (None, Some(id)) => id == k.key_id(),
+                (None, None) => true,
+            })
+    }
+}
+
+/// Verifies a detached signature over `data`. On success, returns a
+/// description of the key that made the signature.
+pub fn verify_detached(
+    keyring: &Keyring,
+    signature: &[u8],
+    mut data: impl Read,
+) -> Result<String, SignatureError> {
+    let signature = dearmor(signature)?;
+    let packets = parse_packets(&signature)?;
+
+    let mut sigs = vec![];
+    for p in packets.iter().filter(|p| p.tag == TAG_SIGNATURE) {
+        if let Some(sig) = Signature::parse(p.body)? {
+            if sig.sig_type == SIG_TYPE_BINARY || sig.sig_type == SIG_TYPE_TEXT {
+                sigs.push(sig);
+            }
+        }
+    }
+    let first_sig = sigs.first().ok_or(SignatureError::NoSignatures)?;
+
+    // Files may be signed by several keys, and we only need one we trust.
+    // Since the data can only be read once, every signature that might be
+    // from a key we know is hashed in the same pass.
+    let mut hashers = vec![];
+    let mut error = None;
+    for sig in sigs
+        .iter()
+        .filter(|s| keyring.candidates(s).next().is_some())
+    {
+        match SigHasher::new(sig.hash_alg, false) {
+            Ok(h) => hashers.push((sig, h)),
+            Err(e) => error = Some(e),
+        }
+    }
+    if hashers.is_empty() {
+        return Err(error.unwrap_or_else(|| {
+            let issuer = first_sig
+                .issuer_fingerprint
+                .or(first_sig.issuer_key_id)
+                .map(format_fingerprint)
+                .unwrap_or_else(|| "unknown".into());
+            SignatureError::NoMatchingKey(issuer)
+        }));
+    }
+
+    let mut buf = vec![0u8; 512 * 1024];
+    let mut canonical = vec![];
+    let mut prev_cr = false;
+    loop {
+        let read = data.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+
+        if hashers.iter().any(|(s, _)| s.sig_type == SIG_TYPE_TEXT) {
+            // Text signatures are made over the text with CRLF line endings.
+            canonical.clear();
+            for &b in &buf[..read] {
+                if b == b'\n' && !prev_cr {
+                    canonical.push(b'\r');
+                }
+                canonical.push(b);
+                prev_cr = b == b'\r';
+            }
+        }
+        for (sig, hasher) in &mut hashers {
+            if sig.sig_type == SIG_TYPE_TEXT {
+                hasher.update(&canonical);
+            } else {
+                hasher.update(&buf[..read]);
+            }
+        }
+    }
+
+    let mut error = SignatureError::BadSignature;
+    for (sig, hasher) in hashers {
+        for (cert, key) in keyring.candidates(sig) {
+            match finish_and_verify(hasher.clone(), sig, key) {
+                Ok(()) => {
+                    return Ok(format!(
+                        "{} ({})",
+                        cert.user_id.as_deref().unwrap_or("[no user ID]"),
+                        format_fingerprint(&cert.primary.fingerprint)
+                    ))
+                }
+                Err(e) => error = e,
+            }
+        }
+    }
+    Err(error)
+}
+
+fn verify_subkey_binding(
+    primary: &PublicKey,
+    subkey: &PublicKey,
+    sig: &Signature,
+) -> Result<(), SignatureError> {
+    verify_key_binding(primary, subkey, sig, primary)
+}
+
+/// Checks that the subkey agreed to being bound to the primary key, so that
+/// nobody can claim someone else's signing key as their own subkey.
+fn verify_back_signature(
+    primary: &PublicKey,
+    subkey: &PublicKey,
+    binding: &Signature,
+) -> Result<(), SignatureError> {
+    let Some(sig) = binding
+        .embedded
+        .map(Signature::parse)
+        .transpose()?
+        .flatten()
+    else {
+        return Err(SignatureError::BadSignature);
+    };
+    if sig.sig_type != SIG_TYPE_PRIMARY_KEY_BINDING {
+        return Err(SignatureError::BadSignature);
+    }
+    verify_key_binding(primary, subkey, &sig, subkey)
+}
+
+/// Checks a signature over a primary key and subkey, made by `signer`.
+fn verify_key_binding(
+    primary: &PublicKey,
+    subkey: &PublicKey,
+    sig: &Signature,
+    signer: &PublicKey,
+) -> Result<(), SignatureError> {
+    // Older keys commonly have SHA-1 binding signatures, so we allow those here.
+    let mut hasher = SigHasher::new(sig.hash_alg, true)?;
+    for key in [primary, subkey] {
+        hasher.update(&[0x99]);
+        hasher.update(&(key.body.len() as u16).to_be_bytes());
+        hasher.update(&key.body);
+    }
+    finish_and_verify(hasher, sig, signer)
+}
+
+/// Hashes the signature's trailer and checks it against the key.
+fn finish_and_verify(
+    mut hasher: SigHasher,
+    sig: &Signature,
+    key: &PublicKey,
+) -> Result<(), SignatureError> {
+    hasher.update(sig.hashed);
+    hasher.update(&[0x04, 0xff]);
+    hasher.update(&(sig.hashed.len() as u32).to_be_bytes());
+
+    let padding = hasher.rsa_padding();
+    let digest = hasher.finalize();
+    if digest[..2] != *sig.hash_prefix {
+        return Err(SignatureError::BadSignature);
+    }
+
+    match (&key.material, &sig.material) {
+        (KeyMaterial::Rsa(k), SignatureMaterial::Rsa(s)) => {
+            // MPIs are stripped of leading zeros, but RSA wants the full length.
+            let mut padded = vec![0u8; k.size().saturating_sub(s.len())];
+            padded.extend_from_slice(s);
+            k.verify(padding, &digest, &padded)
+                .map_err(|_| SignatureError::BadSignature)
+        }
+        (KeyMaterial::Ed25519(k), SignatureMaterial::Ed25519(s)) => k
+            .verify_strict(&digest, &ed25519_dalek::Signature::from_bytes(s))
+            .map_err(|_| SignatureError::BadSignature),
+        _ => Err(SignatureError::BadSignature),
+    }
+}
+
+impl PublicKey {
+    /// Parses a public key packet body. Returns None if the key's version or
+    /// algorithm is not supported.
+    fn parse(body: &[u8]) -> Result<Option<Self>, SignatureError> {
+        let mut r = Reader(body);
+        if r.u8()? != 4 {
+            return Ok(None);
+        }
+        let _created = r.bytes(4)?;
+
+        let material = match r.u8()? {
+            // RSA (Encrypt or Sign), RSA Encrypt-Only, RSA Sign-Only
+            1..=3 => {
+                let n = BigUint::from_bytes_be(r.mpi()?);
+                let e = BigUint::from_bytes_be(r.mpi()?);
+                match RsaPublicKey::new_with_max_size(n, e, 16384) {
+                    Ok(k) => KeyMaterial::Rsa(k),
+                    Err(_) => return Err(SignatureError::Malformed("RSA public key")),
+                }
+            }
+            // EdDSA, in the legacy format
+            22 => {
+                let oid_len = r.u8()? as usize;
+                if r.bytes(oid_len)? != ED25519_LEGACY_OID {
+                    return Ok(None);
+                }
+                let q = r.mpi()?;
+                if q.len() != 33 || q[0] != 0x40 {
+                    return Err(SignatureError::Malformed("Ed25519 public key"));
+                }
+                KeyMaterial::Ed25519(ed25519_key(&q[1..])?)
+            }
+            // Ed25519
+            27 => KeyMaterial::Ed25519(ed25519_key(r.bytes(32)?)?),
+            _ => return Ok(None),
+        };
+
+        let mut fpr_hasher = sha1::Sha1::new();
+        fpr_hasher.update([0x99]);
+        fpr_hasher.update((body.len() as u16).to_be_bytes());
+        fpr_hasher.update(body);
+
+        Ok(Some(Self {
+            fingerprint: fpr_hasher.finalize().into(),
+            material,
+            body: body.to_vec(),
+        }))
+    }
+
+    fn key_id(&self) -> &[u8] {
+        &self.fingerprint[12..]
+    }
+}
+
+fn ed25519_key(bytes: &[u8]) -> Result<VerifyingKey, SignatureError> {
+    bytes
+        .try_into()
+        .ok()
+        .and_then(|b| VerifyingKey::from_bytes(b).ok())
+        .ok_or(SignatureError::Malformed("Ed25519 public key"))
+}
+
+impl<'a> Signature<'a> {
+    /// Parses a signature packet body. Returns None if the signature's version
+    /// or algorithm is not supported.
+    fn parse(body: &'a [u8]) -> Result<Option<Self>, SignatureError> {
+        let mut r = Reader(body);
+        if r.u8()? != 4 {
+            return Ok(None);
+        }
+        let sig_type = r.u8()?;
+        let pubkey_alg = r.u8()?;
+        let hash_alg = r.u8()?;
+
+        let hashed_len = r.u16()? as usize;
+        let hashed_subpackets = r.bytes(hashed_len)?;
+        let hashed = &body[..6 + hashed_len];
+        let unhashed_len = r.u16()? as usize;
+        let unhashed_subpackets = r.bytes(unhashed_len)?;
+        let hash_prefix = r.bytes(2)?;
+
+        let material = match pubkey_alg {
+            1..=3 => SignatureMaterial::Rsa(r.mpi()?.to_vec()),
+            22 => {
+                let mut sig = [0u8; 64];
+                for half in sig.chunks_mut(32) {
+                    let x = r.mpi()?;
+                    if x.len() > 32 {
+                        return Err(SignatureError::Malformed("Ed25519 signature"));
+                    }
+                    half[32 - x.len()..].copy_from_slice(x);
+                }
+                SignatureMaterial::Ed25519(sig)
+            }
+            27 => SignatureMaterial::Ed25519(r.bytes(64)?.try_into().unwrap()),
+            _ => return Ok(None),
+        };
+
+        let mut sig = Self {
+            sig_type,
+            hash_alg,
+            hashed,
+            issuer_key_id: None,
+            issuer_fingerprint: None,
+            key_flags: None,
+            embedded: None,
+            hash_prefix,
+            material,
+        };
+
+        for (is_hashed, area) in [(true, hashed_subpackets), (false, unhashed_subpackets)] {
+            let mut r = Reader(area);
+            while !r.0.is_empty() {
+                let len = r.subpacket_len()?;
+                let sp = r.bytes(len)?;
+                let Some((&sp_type, data)) = sp.split_first() else {
+                    continue;
+                };
+
+                match sp_type & 0x7f {
+                    16 if data.len() == 8 => sig.issuer_key_id = Some(data),
+                    33 if data.len() == 21 && data[0] == 4 => {
+                        sig.issuer_fingerprint = Some(&data[1..])
+                    }
+                    27 if is_hashed => sig.key_flags = data.first().copied(),
+                    32 => sig.embedded = Some(data),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Some(sig))
+    }
+}
+
+macro_rules! sig_hasher {
+    { $($id:literal => $arm:ident($inner:ty),)* } => {
+        #[derive(Clone)]
+        enum SigHasher {
+            $($arm($inner),)*
+        }
+
+        impl SigHasher {
+            fn new(alg: u8, allow_sha1: bool) -> Result<Self, SignatureError> {
+                match alg {
+                    2 if !allow_sha1 => Err(SignatureError::Unsupported(
+                        "insecure SHA-1 signature".into(),
+                    )),
+                    $($id => Ok(Self::$arm(<$inner>::new())),)*
+                    other => Err(SignatureError::Unsupported(format!(
+                        "OpenPGP hash algorithm {other}"
+                    ))),
+                }
+            }
+
+            fn update(&mut self, data: &[u8]) {
+                match self {
+                    $(Self::$arm(h) => h.update(data),)*
+                }
+            }
+
+            fn finalize(self) -> Vec<u8> {
+                match self {
+                    $(Self::$arm(h) => h.finalize().to_vec(),)*
+                }
+            }
+
+            fn rsa_padding(&self) -> Pkcs1v15Sign {
+                match self {
+                    $(Self::$arm(_) => Pkcs1v15Sign::new::<$inner>(),)*
+                }
+            }
+        }
+    };
+}
+
+sig_hasher! {
+    2 => Sha1(sha1::Sha1),
+    8 => Sha256(sha2::Sha256),
+    9 => Sha384(sha2::Sha384),
+    10 => Sha512(sha2::Sha512),
+    11 => Sha224(sha2::Sha224),
+}
+
+struct Packet<'a> {
+    tag: u8,
+    body: &'a [u8],
+}
+
+fn parse_packets(data: &[u8]) -> Result<Vec<Packet<'_>>, SignatureError> {
+    let mut r = Reader(data);
+    let mut packets = vec![];
+
+    while !r.0.is_empty() {
+        let header = r.u8()?;
+        if header & 0x80 == 0 {
+            return Err(SignatureError::Malformed("OpenPGP packet header"));
+        }
+
+        let (tag, len) = if header & 0x40 != 0 {
+            // New format
+            let len = match r.u8()? {
+                o1 @ 0..=191 => o1 as usize,
+                o1 @ 192..=223 => ((o1 as usize - 192) << 8) + r.u8()? as usize + 192,
+                255 => r.u32()? as usize,
+                _ => {
+                    return Err(SignatureError::Unsupported(
+                        "partial-length OpenPGP packets".into(),
+                    ))
+                }
+            };
+            (header & 0x3f, len)
+        } else {
+            // Old format
+            let len = match header & 0x03 {
+                0 => r.u8()? as usize,
+                1 => r.u16()? as usize,
+                2 => r.u32()? as usize,
+                _ => r.0.len(),
+            };
+            ((header >> 2) & 0x0f, len)
+        };
+
+        packets.push(Packet {
+            tag,
+            body: r.bytes(len)?,
+        });
+    }
+
+    Ok(packets)
+}
+
+/// Returns the binary form of the given data, decoding any ASCII armor.
+fn dearmor(data: &[u8]) -> Result<Vec<u8>, SignatureError> {
+    let text = match std::str::from_utf8(data) {
+        Ok(t) if t.contains("-----BEGIN PGP ") => t,
+        _ => return Ok(data.to_vec()),
+    };
+
+    let mut out = vec![];
+    let mut lines = text.lines().map(str::trim_end);
+    while let Some(line) = lines.next() {
+        if !line.starts_with("-----BEGIN PGP ") || line.contains("SIGNED MESSAGE") {
+            continue;
+        }
+
+        let mut b64 = String::new();
+        let mut in_headers = true;
+        for line in lines.by_ref() {
+            if line.starts_with("-----END PGP ") {
+                break;
+            }
+            if in_headers {
+                if line.is_empty() || line.contains(": ") {
+                    continue;
+                }
+                in_headers = false;
+            }
+            // Lines starting with = are the CRC24 checksum.
+            if !line.starts_with('=') {
+                b64.push_str(line);
+            }
+        }
+
+        let block = base64::engine::general_purpose::STANDARD
+            .decode(b64)
+            .map_err(|_| SignatureError::Malformed("ASCII armor"))?;
+        out.extend(block);
+    }
+
+    Ok(out)
+}
+
+fn format_fingerprint(fpr: &[u8]) -> String {
+    let hex = base16::encode_upper(fpr);
+    let groups: Vec<&str> = hex
+        .as_bytes()
+        .chunks(4)
+        .map(|c| std::str::from_utf8(c).unwrap())
+        .collect();
+    groups.join(" ")
+}
+
+/// A cursor over a byte slice that fails on truncated input.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], SignatureError> {
+        if self.0.len() < n {
+            return Err(SignatureError::Malformed("OpenPGP data (truncated)"));
+        }
+        let (out, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, SignatureError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SignatureError> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SignatureError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// Reads a multiprecision integer, returning its big-endian bytes.
+    fn mpi(&mut self) -> Result<&'a [u8], SignatureError> {
+        let bits = self.u16()? as usize;
+        self.bytes(bits.div_ceil(8))
+    }
+
+    fn subpacket_len(&mut self) -> Result<usize, SignatureError> {
+        Ok(match self.u8()? {
+            o1 @ 0..=191 => o1 as usize,
+            o1 @ 192..=254 => ((o1 as usize - 192) << 8) + self.u8()? as usize + 192,
+            255 => self.u32()? as usize,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_detached, Keyring};
+    use crate::signature::SignatureError;
+
+    const DATA: &[u8] = include_bytes!("testdata/data.txt");
+    const SUMS: &[u8] = include_bytes!("testdata/SHA256SUMS");
+    const RSA_KEY: &[u8] = include_bytes!("testdata/rsa.asc");
+    const ED25519_KEY: &[u8] = include_bytes!("testdata/ed25519.gpg");
+    const DATA_RSA_SIG: &[u8] = include_bytes!("testdata/data.txt.rsa.asc");
+    const DATA_ED25519_SIG: &[u8] = include_bytes!("testdata/data.txt.ed25519.sig");
+    const SUMS_TEXT_SIG: &[u8] = include_bytes!("testdata/SHA256SUMS.text.asc");
+
+    #[test]
+    fn rsa_armored_signature() {
+        let keyring = Keyring::parse(RSA_KEY).unwrap();
+
+        let signer = verify_detached(&keyring, DATA_RSA_SIG, DATA).unwrap();
+
+        assert!(signer.starts_with("RSA Test <rsa@example.com>"));
+    }
+
+    #[test]
+    fn ed25519_subkey_binary_signature() {
+        let keyring = Keyring::parse(ED25519_KEY).unwrap();
+
+        let signer = verify_detached(&keyring, DATA_ED25519_SIG, DATA).unwrap();
+
+        assert!(signer.starts_with("Ed25519 Test <ed@example.com>"));
+    }
+
+    #[test]
+    fn text_mode_signature() {
+        let keyring = Keyring::parse(RSA_KEY).unwrap();
+
+        verify_detached(&keyring, SUMS_TEXT_SIG, SUMS).unwrap();
+    }
+
+    #[test]
+    fn keyring_with_multiple_certs() {
+        let mut both = ED25519_KEY.to_vec();
+        both.extend_from_slice(&super::dearmor(RSA_KEY).unwrap());
+        let keyring = Keyring::parse(&both).unwrap();
+
+        verify_detached(&keyring, DATA_RSA_SIG, DATA).unwrap();
+        verify_detached(&keyring, DATA_ED25519_SIG, DATA).unwrap();
+    }
+
+    #[test]
+    fn tampered_data_is_rejected() {
+        let keyring = Keyring::parse(ED25519_KEY).unwrap();
+        let mut data = DATA.to_vec();
+        data[3] ^= 1;
+
+        let err = verify_detached(&keyring, DATA_ED25519_SIG, &data[..]).unwrap_err();
+
+        assert!(matches!(err, SignatureError::BadSignature), "{err:?}");
+    }
+
+    #[test]
+    fn tries_every_signature() {
+        let keyring = Keyring::parse(RSA_KEY).unwrap();
+        // A signature by the same key, but over other data, comes first.
+        let sigs = [SUMS_TEXT_SIG, DATA_RSA_SIG].concat();
+
+        verify_detached(&keyring, &sigs, DATA).unwrap();
+    }
+
+    #[test]
+    fn subkey_without_valid_back_signature_is_ignored() {
+        let mut key = ED25519_KEY.to_vec();
+        // The embedded back-signature subpacket, in the subkey binding's
+        // unhashed area. Its last byte is part of the signature itself.
+        let at = key
+            .windows(4)
+            .position(|w| w == [118, 32, 4, 0x19])
+            .unwrap();
+        key[at + 118] ^= 1;
+        let keyring = Keyring::parse(&key).unwrap();
+
+        let err = verify_detached(&keyring, DATA_ED25519_SIG, DATA).unwrap_err();
+
+        assert!(matches!(err, SignatureError::NoMatchingKey(_)), "{err:?}");
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let keyring = Keyring::parse(RSA_KEY).unwrap();
+
+        let err = verify_detached(&keyring, DATA_ED25519_SIG, DATA).unwrap_err();
+
+        assert!(matches!(err, SignatureError::NoMatchingKey(_)), "{err:?}");
+    }
+}
@@ -0,0 +1,150 @@
+//! Verification of OpenBSD [signify](https://man.openbsd.org/signify) signatures.
+//!
+//! Only detached signatures are supported, not ones embedded in the signed
+//! message with `signify -e`.
+
+use std::io::Read;
+
+use base64::Engine;
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use super::{read_message, SignatureError};
+
+const PKALG: &[u8] = b"Ed";
+
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    key_num: [u8; 8],
+    key: VerifyingKey,
+    comment: Option<String>,
+}
+
+impl PublicKey {
+    /// Parses the contents of a signify `.pub` file.
+    pub fn parse(data: &[u8]) -> Result<Self, SignatureError> {
+        let (comment, bin) = decode_file(data, "signify public key")?;
+        let (key_num, key) = split_payload(&bin, 32, "signify public key")?;
+
+        let key = VerifyingKey::from_bytes(key.try_into().unwrap())
+            .map_err(|_| SignatureError::Malformed("signify public key"))?;
+
+        Ok(Self {
+            key_num,
+            key,
+            comment,
+        })
+    }
+}
+
+/// Verifies a signify signature over `data`. On success, returns a
+/// description of the key that made the signature.
+pub fn verify_detached(
+    key: &PublicKey,
+    signature: &[u8],
+    data: impl Read,
+) -> Result<String, SignatureError> {
+    let (_, bin) = decode_file(signature, "signify signature")?;
+    let (key_num, sig) = split_payload(&bin, 64, "signify signature")?;
+
+    if key_num != key.key_num {
+        return Err(SignatureError::NoMatchingKey(base16::encode_upper(
+            &key_num,
+        )));
+    }
+
+    let message = read_message(data, "signify signature")?;
+    let sig = Signature::from_bytes(sig.try_into().unwrap());
+    key.key
+        .verify_strict(&message, &sig)
+        .map_err(|_| SignatureError::BadSignature)?;
+
+    Ok(match &key.comment {
+        Some(c) => format!("{c} ({})", base16::encode_upper(&key.key_num)),
+        None => format!("key {}", base16::encode_upper(&key.key_num)),
+    })
+}
+
+/// Decodes a signify file, which is an untrusted comment line followed by a
+/// line of base64.
+fn decode_file(
+    data: &[u8],
+    what: &'static str,
+) -> Result<(Option<String>, Vec<u8>), SignatureError> {
+    let text = std::str::from_utf8(data).map_err(|_| SignatureError::Malformed(what))?;
+    let mut lines = text.lines();
+
+    let comment = lines
+        .next()
+        .and_then(|l| l.strip_prefix("untrusted comment:"))
+        .ok_or(SignatureError::Malformed(what))?
+        .trim();
+    let bin = lines
+        .next()
+        .and_then(|l| {
+            base64::engine::general_purpose::STANDARD
+                .decode(l.trim())
+                .ok()
+        })
+        .ok_or(SignatureError::Malformed(what))?;
+
+    let comment = (!comment.is_empty()).then(|| comment.to_owned());
+    Ok((comment, bin))
+}
+
+/// Splits the decoded payload into its key number and the key or signature.
+fn split_payload<'a>(
+    bin: &'a [u8],
+    len: usize,
+    what: &'static str,
+) -> Result<([u8; 8], &'a [u8]), SignatureError> {
+    if bin.len() != 2 + 8 + len {
+        return Err(SignatureError::Malformed(what));
+    }
+    if &bin[..2] != PKALG {
+        return Err(SignatureError::Unsupported(format!(
+            "signify algorithm {:?}",
+            String::from_utf8_lossy(&bin[..2])
+        )));
+    }
+    Ok((bin[2..10].try_into().unwrap(), &bin[10..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_detached, PublicKey};
+    use crate::signature::SignatureError;
+
+    const DATA: &[u8] = include_bytes!("testdata/data.txt");
+    const PUBKEY: &[u8] = include_bytes!("testdata/signify.pub");
+    const MINISIGN_PUBKEY: &[u8] = include_bytes!("testdata/minisign.pub");
+    const DATA_SIG: &[u8] = include_bytes!("testdata/data.txt.signify.sig");
+
+    #[test]
+    fn good_signature() {
+        let key = PublicKey::parse(PUBKEY).unwrap();
+
+        let signer = verify_detached(&key, DATA_SIG, DATA).unwrap();
+
+        assert_eq!(signer, "test signify public key (A1B2C3D4E5F60718)");
+    }
+
+    #[test]
+    fn tampered_data_is_rejected() {
+        let key = PublicKey::parse(PUBKEY).unwrap();
+        let mut data = DATA.to_vec();
+        data.push(b'\n');
+
+        let err = verify_detached(&key, DATA_SIG, &data[..]).unwrap_err();
+
+        assert!(matches!(err, SignatureError::BadSignature), "{err:?}");
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let key = PublicKey::parse(MINISIGN_PUBKEY).unwrap();
+
+        let err = verify_detached(&key, DATA_SIG, DATA).unwrap_err();
+
+        assert!(matches!(err, SignatureError::NoMatchingKey(_)), "{err:?}");
+    }
+}
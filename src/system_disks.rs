@@ -0,0 +1,164 @@
+//! Working out which disks the running system lives on, so that we never
+//! overwrite them by accident.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use tracing::debug;
+
+#[cfg(target_os = "linux")]
+use crate::mounts::MountTable;
+
+/// Paths that the running system can't live without.
+#[cfg(target_os = "linux")]
+const SYSTEM_PATHS: &[&str] = &["/", "/boot", "/boot/efi"];
+
+/// The block devices that the running system is on.
+#[derive(Debug, Default, Clone)]
+pub struct SystemDisks {
+    /// Canonical sysfs paths of every block device that the system paths are
+    /// on, along with everything underneath them, like the partitions that
+    /// make up an LVM volume or LUKS container.
+    devices: Vec<PathBuf>,
+}
+
+impl SystemDisks {
+    #[cfg(target_os = "linux")]
+    pub fn load() -> io::Result<Self> {
+        let mounts = MountTable::load()?;
+
+        // Our state directory is somewhere in here. If it goes, so do our
+        // logs and the socket we talk to the writer over.
+        let temp_dir = std::env::temp_dir();
+        let paths = SYSTEM_PATHS
+            .iter()
+            .map(Path::new)
+            .chain([temp_dir.as_path()])
+            .chain(mounts.swaps().iter().map(PathBuf::as_path));
+
+        let mut devices = vec![];
+        for path in paths {
+            match sys_device_of_path(&mounts, path) {
+                Ok(Some(dev)) => devices.push(dev),
+                Ok(None) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Follow device mapper devices down to the disks they're made of.
+        let mut i = 0;
+        while i < devices.len() {
+            if let Ok(slaves) = std::fs::read_dir(devices[i].join("slaves")) {
+                for slave in slaves {
+                    let slave = slave?.path().canonicalize()?;
+                    if !devices.contains(&slave) {
+                        devices.push(slave);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        debug!(?devices, "Found system devices");
+        Ok(Self { devices })
+    }
+
+    /// There's no sysfs to go through, so nothing is protected.
+    #[cfg(not(target_os = "linux"))]
+    pub fn load() -> io::Result<Self> {
+        Ok(Self::default())
+    }
+
+    /// Whether writing to `devnode` would overwrite any part of the system.
+    pub fn contains(&self, devnode: &Path) -> io::Result<bool> {
+        let Some(dev) = sys_device_of_node(devnode)? else {
+            return Ok(false);
+        };
+        Ok(self.overlaps(&dev))
+    }
+
+    /// Whether `dev` is one of the system devices, or a disk with one of them
+    /// as a partition.
+    fn overlaps(&self, dev: &Path) -> bool {
+        self.devices.iter().any(|d| d.starts_with(dev))
+    }
+}
+
+/// The sysfs path of the block device that `path` is on, if it is on one.
+#[cfg(target_os = "linux")]
+fn sys_device_of_path(mounts: &MountTable, path: &Path) -> io::Result<Option<PathBuf>> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let meta = std::fs::metadata(path)?;
+    if meta.file_type().is_block_device() {
+        return sys_device_of_dev(meta.rdev());
+    }
+    if let Some(dev) = sys_device_of_dev(meta.dev())? {
+        return Ok(Some(dev));
+    }
+
+    // Filesystems like btrfs and ZFS report a made-up device number, so go
+    // by what the filesystem was mounted from instead.
+    match mounts.source_of(&path.canonicalize()?) {
+        Some(source) => sys_device_of_node(source),
+        None => Ok(None),
+    }
+}
+
+/// The sysfs path of `devnode`, if it is a block device.
+fn sys_device_of_node(devnode: &Path) -> io::Result<Option<PathBuf>> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    match std::fs::metadata(devnode) {
+        Ok(meta) if meta.file_type().is_block_device() => sys_device_of_dev(meta.rdev()),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sys_device_of_dev(dev: u64) -> io::Result<Option<PathBuf>> {
+    let path = PathBuf::from(format!(
+        "/sys/dev/block/{}:{}",
+        libc::major(dev),
+        libc::minor(dev)
+    ));
+    match path.canonicalize() {
+        Ok(p) => Ok(Some(p)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sys_device_of_dev(_dev: u64) -> io::Result<Option<PathBuf>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::SystemDisks;
+
+    #[test_case("/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda" => true; "disk with system partition")]
+    #[test_case("/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda/sda2" => true; "system partition")]
+    #[test_case("/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda/sda3" => false; "other partition")]
+    #[test_case("/sys/devices/virtual/block/dm-0" => true; "device mapper")]
+    #[test_case("/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb" => false; "other disk")]
+    fn overlaps(dev: &str) -> bool {
+        let disks = SystemDisks {
+            devices: vec![
+                "/sys/devices/virtual/block/dm-0".into(),
+                "/sys/devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda/sda2"
+                    .into(),
+            ],
+        };
+
+        disks.overlaps(dev.as_ref())
+    }
+}
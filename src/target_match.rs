@@ -0,0 +1,351 @@
+//! Picking the target by what it is, rather than by its name, which can
+//! change between boots. For example, `serial=ABC123` or
+//! `model=Extreme*,size<64G`.
+
+use std::{fmt::Display, str::FromStr};
+
+use bytesize::ByteSize;
+use itertools::Itertools;
+
+use crate::device::{Type, WriteTarget};
+
+/// A set of conditions that the target must all meet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetMatch {
+    expr: String,
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    /// A field matching a pattern, where `*` matches anything and `?` matches
+    /// any one character. Case is ignored.
+    Text {
+        field: Field,
+        pattern: String,
+        negated: bool,
+    },
+    Size {
+        op: SizeOp,
+        size: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Name,
+    /// The device node, or its `/dev/disk/by-id` link.
+    Path,
+    Serial,
+    Model,
+    Vendor,
+    Wwn,
+    Transport,
+    Type,
+    Removable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SizeOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TargetMatchError {
+    #[error("No device matches {0}")]
+    NoMatch(String),
+    #[error("{expr} matches more than one device: {devices}")]
+    Ambiguous { expr: String, devices: String },
+}
+
+impl FromStr for TargetMatch {
+    type Err = String;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let conditions = expr
+            .split(',')
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .map(Condition::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        if conditions.is_empty() {
+            return Err("expected at least one condition, like serial=ABC123".into());
+        }
+        Ok(Self {
+            expr: expr.to_owned(),
+            conditions,
+        })
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Longer operators go first, so that `<=` isn't taken for `<`.
+        const OPS: [(&str, SizeOp); 6] = [
+            ("<=", SizeOp::Le),
+            (">=", SizeOp::Ge),
+            ("!=", SizeOp::Ne),
+            ("<", SizeOp::Lt),
+            (">", SizeOp::Gt),
+            ("=", SizeOp::Eq),
+        ];
+        let Some((key, op, value)) = OPS
+            .iter()
+            .filter_map(|(token, op)| {
+                let (key, value) = s.split_once(token)?;
+                Some((key, *op, value))
+            })
+            .min_by_key(|(key, _, _)| key.len())
+        else {
+            return Err(format!("expected a condition like key=value, got {s:?}"));
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+
+        let field = match key.as_str() {
+            "size" => {
+                let size = ByteSize::from_str(value)
+                    .map_err(|e| format!("invalid size {value:?}: {e}"))?;
+                return Ok(Condition::Size { op, size: size.0 });
+            }
+            "name" => Field::Name,
+            "path" => Field::Path,
+            "serial" => Field::Serial,
+            "model" => Field::Model,
+            "vendor" => Field::Vendor,
+            "wwn" => Field::Wwn,
+            "transport" => Field::Transport,
+            "type" => Field::Type,
+            "removable" => Field::Removable,
+            _ => {
+                return Err(format!(
+                    "unknown key {key:?}, expected one of name, path, serial, model, vendor, \
+                     wwn, transport, type, removable or size"
+                ))
+            }
+        };
+        let negated = match op {
+            SizeOp::Eq => false,
+            SizeOp::Ne => true,
+            _ => return Err(format!("{key} can only be compared with = or !=")),
+        };
+        Ok(Condition::Text {
+            field,
+            pattern: value.to_owned(),
+            negated,
+        })
+    }
+}
+
+impl Display for TargetMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.expr)
+    }
+}
+
+impl TargetMatch {
+    /// Whether `target` meets every condition. Partitions share most of their
+    /// details with their disk, so they are only matched when asked for by
+    /// type, name or path.
+    pub fn matches(&self, target: &WriteTarget) -> bool {
+        let asks_for_partitions = self.conditions.iter().any(|c| {
+            matches!(
+                c,
+                Condition::Text {
+                    field: Field::Type | Field::Name | Field::Path,
+                    negated: false,
+                    ..
+                }
+            )
+        });
+        if target.target_type == Type::Partition && !asks_for_partitions {
+            return false;
+        }
+        self.conditions.iter().all(|c| c.matches(target))
+    }
+
+    /// Finds the one device that matches.
+    pub fn resolve(
+        &self,
+        devices: impl IntoIterator<Item = WriteTarget>,
+    ) -> Result<WriteTarget, TargetMatchError> {
+        let mut found: Vec<WriteTarget> = devices.into_iter().filter(|d| self.matches(d)).collect();
+        match found.len() {
+            0 => Err(TargetMatchError::NoMatch(self.to_string())),
+            1 => Ok(found.remove(0)),
+            _ => Err(TargetMatchError::Ambiguous {
+                expr: self.to_string(),
+                devices: found.iter().map(|d| &d.name).format(", ").to_string(),
+            }),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, target: &WriteTarget) -> bool {
+        match self {
+            Condition::Size { op, size } => {
+                let Some(actual) = target.size.bytes() else {
+                    return false;
+                };
+                match op {
+                    SizeOp::Eq => actual == *size,
+                    SizeOp::Ne => actual != *size,
+                    SizeOp::Lt => actual < *size,
+                    SizeOp::Le => actual <= *size,
+                    SizeOp::Gt => actual > *size,
+                    SizeOp::Ge => actual >= *size,
+                }
+            }
+            Condition::Text {
+                field,
+                pattern,
+                negated,
+            } => {
+                let values = field.values(target);
+                values.iter().any(|v| glob_match(pattern, v)) != *negated
+            }
+        }
+    }
+}
+
+impl Field {
+    /// The values of this field on `target`. Most fields have one value, or
+    /// none if it's unknown.
+    fn values(&self, target: &WriteTarget) -> Vec<String> {
+        let details = &target.details;
+        match self {
+            Field::Name => vec![target.name.clone()],
+            Field::Path => [Some(&target.devnode), details.by_id.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect(),
+            Field::Serial => details.serial.iter().cloned().collect(),
+            Field::Model => target
+                .model
+                .as_deref()
+                .map(str::to_owned)
+                .into_iter()
+                .collect(),
+            Field::Vendor => details.vendor.iter().cloned().collect(),
+            Field::Wwn => details.wwn.iter().cloned().collect(),
+            Field::Transport => vec![details.transport.to_string()],
+            Field::Type => vec![target.target_type.to_string()],
+            Field::Removable => vec![target.removable.to_string()],
+        }
+    }
+}
+
+/// Matches `text` against a pattern, where `*` matches any number of
+/// characters and `?` matches exactly one. Case is ignored.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn go(pattern: &[char], text: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len()).any(|i| go(rest, &text[i..])),
+            Some((p, rest)) => match text.split_first() {
+                Some((t, text)) => (*p == '?' || p == t) && go(rest, text),
+                None => false,
+            },
+        }
+    }
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    go(&pattern, &text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bytesize::ByteSize;
+    use test_case::test_case;
+
+    use crate::device::{
+        DeviceDetails, Model, Removable, TargetSize, Transport, Type, WriteTarget,
+    };
+
+    use super::{glob_match, TargetMatch};
+
+    fn device(name: &str, serial: &str, model: &str, size: u64, target_type: Type) -> WriteTarget {
+        WriteTarget {
+            name: name.into(),
+            devnode: PathBuf::from("/dev").join(name),
+            size: TargetSize::from(Some(ByteSize::b(size))),
+            model: Model::from(Some(model.to_owned())),
+            removable: Removable::Yes,
+            target_type,
+            details: DeviceDetails {
+                serial: Some(serial.into()),
+                transport: Transport::Usb,
+                by_id: Some(
+                    format!(
+                        "/dev/disk/by-id/usb-{}_{serial}-0:0",
+                        model.replace(' ', "_")
+                    )
+                    .into(),
+                ),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn devices() -> Vec<WriteTarget> {
+        vec![
+            device(
+                "sda",
+                "S4EVNF0M",
+                "Samsung SSD 970",
+                500_000_000_000,
+                Type::Disk,
+            ),
+            device("sdb", "ABC123", "Extreme", 32_000_000_000, Type::Disk),
+            device("sdb1", "ABC123", "Extreme", 31_000_000_000, Type::Partition),
+            device("sdc", "DEF456", "Extreme Pro", 128_000_000_000, Type::Disk),
+        ]
+    }
+
+    #[test_case("serial=ABC123" => Ok("sdb".into()); "serial")]
+    #[test_case("model=Extreme*,size<64G" => Ok("sdb".into()); "model and size")]
+    #[test_case("model=extreme*, size >= 64G" => Ok("sdc".into()); "case and spaces")]
+    #[test_case("serial=ABC123,type=partition" => Ok("sdb1".into()); "partition")]
+    #[test_case("path=/dev/disk/by-id/usb-Extreme_Pro_*" => Ok("sdc".into()); "by id")]
+    #[test_case("transport=usb,model!=Extreme*" => Ok("sda".into()); "negated")]
+    #[test_case("model=Extreme*" => Err("ambiguous"); "ambiguous")]
+    #[test_case("serial=XYZ" => Err("none"); "no match")]
+    fn resolve(expr: &str) -> Result<String, &'static str> {
+        let m: TargetMatch = expr.parse().unwrap();
+        match m.resolve(devices()) {
+            Ok(t) => Ok(t.name),
+            Err(super::TargetMatchError::NoMatch(_)) => Err("none"),
+            Err(super::TargetMatchError::Ambiguous { .. }) => Err("ambiguous"),
+        }
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("serial"; "no operator")]
+    #[test_case("colour=blue"; "unknown key")]
+    #[test_case("serial<ABC"; "ordering text")]
+    #[test_case("size<lots"; "bad size")]
+    fn parse_invalid(expr: &str) {
+        expr.parse::<TargetMatch>().unwrap_err();
+    }
+
+    #[test_case("Extreme*", "Extreme Pro" => true; "star")]
+    #[test_case("*treme", "Extreme" => true; "leading star")]
+    #[test_case("ABC12?", "ABC123" => true; "question mark")]
+    #[test_case("ABC12?", "ABC1234" => false; "question mark is one character")]
+    #[test_case("abc123", "ABC123" => true; "case")]
+    #[test_case("Extreme", "Extreme Pro" => false; "exact")]
+    fn glob(pattern: &str, text: &str) -> bool {
+        glob_match(pattern, text)
+    }
+}
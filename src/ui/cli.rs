@@ -1,12 +1,16 @@
 use is_terminal::IsTerminal;
-use itertools::Itertools;
 use std::{fmt::Display, path::PathBuf};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use valuable::Valuable;
 
 use crate::{
-    compression::CompressionArg,
+    compression::{CompressionArg, CompressionFormat},
     hash::{parse_hash_input, HashAlg},
+    multipart::ImageParts,
+    target_match::TargetMatch,
+    writer_process::ipc::VerifyStrategy,
 };
 
 /// A safe, user-friendly disk imager.
@@ -26,16 +30,48 @@ pub enum Command {
 /// Burn an image to a disk.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(group(
+    ArgGroup::new("signature_key")
+        .args(["keyring", "minisign_pubkey", "signify_pubkey"])
+        .requires("signature")
+))]
 pub struct BurnArgs {
     /// Input file to burn.
+    ///
+    /// If this is the first part of a split image (i.e. `image.iso.000` or
+    /// `image.iso.partaa`), the following parts will be detected and burned
+    /// along with it.
     #[arg(value_parser = parse_path_exists)]
     pub input: PathBuf,
 
+    /// Remaining parts of a split image, in order. If provided, part
+    /// detection is skipped and exactly these files are concatenated after
+    /// the input file.
+    #[arg(value_parser = parse_path_exists)]
+    pub more_parts: Vec<PathBuf>,
+
     /// Where to write the output. If not supplied, we will search for possible
     /// disks and ask you for where you want to burn.
+    ///
+    /// Links like `/dev/disk/by-id/...` and `/dev/disk/by-path/...` may be
+    /// given, which unlike `/dev/sdX` don't change between boots.
     #[arg(short)]
     pub out: Option<PathBuf>,
 
+    /// Pick the output by what it is, rather than by its name. This must match
+    /// exactly one device.
+    ///
+    /// This is a comma-separated list of conditions, which must all hold, like
+    /// `serial=ABC123` or `model=Extreme*,size<64G`. The keys are `name`,
+    /// `path`, `serial`, `model`, `vendor`, `wwn`, `transport`, `type`,
+    /// `removable` and `size`. Text may be compared with `=` or `!=`, and may
+    /// contain `*` and `?` wildcards. Sizes may also be compared with `<`,
+    /// `<=`, `>` and `>=`.
+    ///
+    /// Partitions are only matched if `type`, `name` or `path` asks for them.
+    #[arg(long, conflicts_with = "out")]
+    pub target_match: Option<TargetMatch>,
+
     /// What compression format the input file is in.
     ///
     ///  - `auto` will guess based on the file extension.
@@ -57,9 +93,15 @@ pub struct BurnArgs {
     ///  - an SRI-like string with either base16 or base64 in the format of `<alg>-<hash>`
     ///    (i.e. `sha256-EVSTQN3/azprGF...`)
     ///
-    ///  - just a hash value, and we will guess the algorithm (i.e. `EVSTQN3/azprGF...`)
+    ///  - just a hash value, and we will guess the algorithm (i.e. `EVSTQN3/azprGF...`).
+    ///    Where several algorithms have the same length, SHA-2 is assumed, so
+    ///    i.e. BLAKE3 hashes need a `blake3-` prefix.
+    ///
+    /// The following algorithms are supported: crc32, md5, sha1, sha224, sha256, sha384,
+    /// sha512, sha3-256, sha3-384, sha3-512, blake2b512, blake3
     ///
-    /// The following algorithms are supported: md5, sha1, sha224, sha256, sha384, sha512
+    /// This may be given several times to check several hashes, which are all
+    /// computed in the same pass over the input.
     #[arg(
         short = 's',
         long,
@@ -67,12 +109,105 @@ pub struct BurnArgs {
         default_value = "ask",
         help = "The hash of the input file. For more information, see long help (--help)"
     )]
-    pub hash: HashArg,
+    pub hash: Vec<HashArg>,
+
+    /// A checksum file to look up the hash of the input file in, like
+    /// `SHA256SUMS` or `image.iso.sha256`.
+    ///
+    /// Both GNU coreutils (`sha256sum`) and BSD-style (`sha256sum --tag`) files
+    /// are supported. The algorithm is guessed from the file's name or from the
+    /// digest's length.
+    #[arg(long, value_parser = parse_path_exists, conflicts_with = "hash")]
+    pub hash_file: Option<PathBuf>,
+
+    /// A detached signature to verify before trusting the input. This may be
+    /// an OpenPGP (`.sig`, `.asc` or `.gpg`), minisign (`.minisig`) or signify
+    /// (`.sig`) signature, depending on which kind of key is given.
+    ///
+    /// If `--hash-file` is given, the signature is checked against that file.
+    /// Otherwise, it is checked against the input file itself, even if the hash
+    /// comes from a checksum file that was found next to it.
+    #[arg(long, value_parser = parse_path_exists, requires = "signature_key")]
+    pub signature: Option<PathBuf>,
+
+    /// The OpenPGP public keys to trust when verifying `--signature`, either
+    /// binary or ASCII-armored.
+    #[arg(long, value_parser = parse_path_exists)]
+    pub keyring: Option<PathBuf>,
+
+    /// The minisign public key to trust when verifying `--signature`, either
+    /// as a `minisign.pub` file or as the base64 key itself.
+    #[arg(long)]
+    pub minisign_pubkey: Option<String>,
+
+    /// The signify public key file to trust when verifying `--signature`.
+    #[arg(long, value_parser = parse_path_exists)]
+    pub signify_pubkey: Option<PathBuf>,
 
     /// Is the hash calculated from the raw file, or the compressed file?
+    ///
+    /// `auto` hashes both at once, and accepts whichever one matches.
     #[arg(long)]
     pub hash_of: Option<HashOf>,
 
+    /// Check the hash while writing, rather than in a separate pass before
+    /// writing.
+    ///
+    /// This saves reading (and decompressing) the input one more time, but if
+    /// the input turns out to be corrupted, you only find out once it has
+    /// been written over whatever was on the disk.
+    #[arg(long)]
+    pub hash_while_writing: bool,
+
+    /// Digests of the image to compute while writing it, and report at the
+    /// end, i.e. `--digest md5,sha256`. These are of the decompressed image,
+    /// which is what ends up on the disk.
+    ///
+    /// These are only reported, not checked against anything.
+    #[arg(long, value_parser = parse_hash_alg, value_delimiter = ',')]
+    pub digest: Vec<HashAlg>,
+
+    /// Whether to check the checksum implanted into the ISO before burning, for
+    /// ISOs that have one (like Fedora's, as checked by `checkisomd5`).
+    ///
+    /// Unless this is `never`, the disk is also checked against it after
+    /// writing.
+    #[arg(long, default_value = "ask")]
+    pub check_iso_md5: CheckIsoMd5,
+
+    /// Hash the input again even if its hash is cached from a previous run.
+    ///
+    /// Hashes computed before writing are remembered for each file, and reused
+    /// as long as the file is not modified.
+    #[arg(long)]
+    pub rehash: bool,
+
+    /// How to check the disk after writing.
+    ///
+    ///  - `compare` reads the input again and compares it with the disk.
+    ///
+    ///  - `hash` hashes the data while writing it, then hashes the disk. This
+    ///    avoids decompressing the input a second time.
+    ///
+    ///  - `auto` uses `hash` for compressed inputs, and `compare` otherwise.
+    #[arg(long, default_value = "auto")]
+    pub verify_by: VerifyArg,
+
+    /// How to make sure that what's written actually reaches the disk.
+    ///
+    ///  - `direct` bypasses the page cache with O_DIRECT, writing in whole
+    ///    blocks of the disk. If the disk doesn't support it, `sync` is used
+    ///    instead.
+    ///
+    ///  - `sync` waits for every write to reach the disk (O_SYNC).
+    ///
+    ///  - `buffered` writes through the page cache, and waits for it to be
+    ///    flushed to the disk every few megabytes.
+    ///
+    /// This has no effect when writing to a file.
+    #[arg(long, default_value = "direct")]
+    pub sync: SyncStrategy,
+
     /// If provided, we will show all disks, removable or not.
     ///
     /// If you use this option, please proceed with caution!
@@ -87,14 +222,68 @@ pub struct BurnArgs {
     pub interactive: Interactive,
 
     /// If supplied, we will not ask for confirmation before destroying your disk.
+    ///
+    /// Targets that aren't in the allowlist are still refused, if there is
+    /// one. The allowlist is read from `/etc/caligula/allowed-targets` and
+    /// from `allowed-targets` in your caligula config directory. Each line is
+    /// a `serial=...`, `by-id=/dev/disk/by-id/...` or
+    /// `by-path=/dev/disk/by-path/...` entry.
     #[arg(short, long)]
     pub force: bool,
 
+    /// What to do if the target disk or any of its partitions are mounted.
+    ///
+    ///  - `ask` offers to unmount them, unless `--force` is given, in which
+    ///    case nothing is written.
+    ///
+    ///  - `unmount` unmounts them without asking.
+    ///
+    ///  - `ignore` writes over them anyway, which will likely corrupt them.
+    ///
+    /// Disks that are part of a RAID array, LVM volume or open LUKS container
+    /// can't be unmounted by us. They need to be confirmed, or with
+    /// `--force`, need `ignore`.
+    #[arg(long, default_value = "ask")]
+    pub if_mounted: IfMounted,
+
+    /// Allow writing to the disk that the running system is on.
+    ///
+    /// This will destroy your system. The name is long on purpose, so that it
+    /// is never typed by accident.
+    #[arg(long, hide_short_help = true)]
+    pub i_really_want_to_overwrite_my_system_disk: bool,
+
     /// If we don't have permissions on the output file, should we try to become root?
     #[arg(long, default_value = "ask")]
     pub root: UseSudo,
 }
 
+impl BurnArgs {
+    /// All the files making up the input image, in order.
+    pub fn image_parts(&self) -> ImageParts {
+        if self.more_parts.is_empty() {
+            ImageParts::detect(&self.input)
+        } else {
+            ImageParts::explicit(
+                [self.input.clone()]
+                    .into_iter()
+                    .chain(self.more_parts.iter().cloned())
+                    .collect(),
+            )
+        }
+    }
+
+    /// How the writer should verify the disk, given the input's compression.
+    pub fn verify_strategy(&self, cf: CompressionFormat) -> VerifyStrategy {
+        match self.verify_by {
+            VerifyArg::Auto if cf.is_identity() => VerifyStrategy::Compare,
+            VerifyArg::Auto => VerifyStrategy::Hash,
+            VerifyArg::Compare => VerifyStrategy::Compare,
+            VerifyArg::Hash => VerifyStrategy::Hash,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HashArg {
     Ask,
@@ -102,15 +291,26 @@ pub enum HashArg {
     Hash {
         alg: HashAlg,
         expected_hash: Vec<u8>,
+        /// Whether `alg` was assumed, because the hash was given without an
+        /// algorithm, and others make hashes of the same length.
+        assumed: bool,
     },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize, Valuable)]
 pub enum HashOf {
+    Auto,
     Raw,
     Compressed,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize, Valuable)]
+pub enum SyncStrategy {
+    Direct,
+    Sync,
+    Buffered,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum Interactive {
     Auto,
@@ -118,6 +318,27 @@ pub enum Interactive {
     Never,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VerifyArg {
+    Auto,
+    Compare,
+    Hash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CheckIsoMd5 {
+    Ask,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IfMounted {
+    Ask,
+    Unmount,
+    Ignore,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum UseSudo {
     Ask,
@@ -128,29 +349,37 @@ pub enum UseSudo {
 fn parse_path_exists(p: &str) -> Result<PathBuf, String> {
     let path = PathBuf::from(p);
     if !path.exists() {
-        return Err(format!("path does not exist"));
+        return Err("path does not exist".to_string());
     }
     Ok(path)
 }
 
+fn parse_hash_alg(alg: &str) -> Result<HashAlg, String> {
+    HashAlg::from_sri_alg(&alg.to_lowercase()).ok_or_else(|| {
+        format!(
+            "unknown algorithm, expected one of: {}",
+            HashAlg::ALL
+                .iter()
+                .map(|a| a.sri_alg())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })
+}
+
 fn parse_hash_arg(h: &str) -> Result<HashArg, String> {
     match h.to_lowercase().as_ref() {
         "ask" => Ok(HashArg::Ask),
         "skip" | "none" => Ok(HashArg::Skip),
         _ => match parse_hash_input(h) {
-            Ok((alg, expected_hash)) => {
-                if alg.len() > 1 {
-                    Err(format!(
-                        "Ambiguous hash algorithm! Could be one of: {}. Please specify by prepending [alg]- to your hash.",
-                        alg.iter().format(", ")
-                    ))
-                } else {
-                    Ok(HashArg::Hash {
-                        alg: alg[0],
-                        expected_hash,
-                    })
-                }
-            }
+            // Bare hashes of a length shared by several algorithms are taken
+            // to be SHA-2, which is listed first, since that's what they almost
+            // always are. Others can be picked with a prefix.
+            Ok((algs, expected_hash)) => Ok(HashArg::Hash {
+                alg: algs[0],
+                expected_hash,
+                assumed: algs.len() > 1,
+            }),
             Err(e) => Err(format!("{e}")),
         },
     }
@@ -159,12 +388,23 @@ fn parse_hash_arg(h: &str) -> Result<HashArg, String> {
 impl Display for HashOf {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            HashOf::Auto => write!(f, "raw or compressed"),
             HashOf::Raw => write!(f, "raw"),
             HashOf::Compressed => write!(f, "compressed"),
         }
     }
 }
 
+impl Display for SyncStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncStrategy::Direct => write!(f, "direct I/O"),
+            SyncStrategy::Sync => write!(f, "synchronous writes"),
+            SyncStrategy::Buffered => write!(f, "buffered, syncing periodically"),
+        }
+    }
+}
+
 impl Interactive {
     pub fn is_interactive(&self) -> bool {
         match self {
@@ -197,11 +437,25 @@ mod tests {
                 alg: HashAlg::Sha384,
                 expected_hash: base64::engine::general_purpose::STANDARD
                     .decode("EVSTQN3/azprG1Anm3QDgpJLIm9Nao0Yz1ztcQTwFspd3yD65VohhpuuCOmLASjC")
-                    .unwrap()
+                    .unwrap(),
+                assumed: false,
             }
         )
     }
 
+    #[test_case(32 => (HashAlg::Sha256, true); "sha256")]
+    #[test_case(64 => (HashAlg::Sha512, true); "sha512")]
+    #[test_case(48 => (HashAlg::Sha384, true); "sha384")]
+    #[test_case(20 => (HashAlg::Sha1, false); "sha1 is not shared")]
+    fn parse_bare_hash_assumes_sha2(bytes: usize) -> (HashAlg, bool) {
+        let hex = base16::encode_lower(&vec![0xab; bytes]);
+
+        match parse_hash_arg(&hex).unwrap() {
+            HashArg::Hash { alg, assumed, .. } => (alg, assumed),
+            other => panic!("expected a hash, got {other:?}"),
+        }
+    }
+
     #[test_case("skip")]
     #[test_case("none")]
     #[test_case("NONE"; "caps")]
@@ -0,0 +1,458 @@
+//! The list of disks to burn to. It updates itself as disks are plugged in
+//! and pulled out, and jumps to disks as soon as they're plugged in.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    io::IsTerminal,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use inquire::InquireError;
+use itertools::Itertools;
+use ratatui::{
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::Span,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+use tracing::{debug, warn};
+
+use crate::{
+    device::{enumerate_devices, Removable, WriteTarget},
+    hotplug::BlockDeviceWatcher,
+    mounts::MountTable,
+    ui::utils::InlineTUI,
+};
+
+/// How many lines of the terminal the picker takes up.
+const HEIGHT: u16 = 12;
+
+/// How often to look for input while waiting for devices to change.
+const TICK: Duration = Duration::from_millis(250);
+
+/// How often to list the devices again, if we can't be told when they change.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Asks the user to pick a disk, keeping the list up to date until they do.
+#[tracing::instrument]
+pub fn pick_device(show_all_disks: bool) -> anyhow::Result<WriteTarget> {
+    if !std::io::stdin().is_terminal() {
+        Err(InquireError::NotTTY)?;
+    }
+
+    let mut watcher = BlockDeviceWatcher::new()
+        .map_err(|e| warn!("Could not watch for devices, polling instead: {e}"))
+        .ok();
+
+    let mut state = PickerState::new(show_all_disks);
+    state.update(list_devices()?);
+    let mut last_refresh = Instant::now();
+
+    let mut tui = InlineTUI::new(HEIGHT)?;
+    let picked = loop {
+        tui.terminal().draw(|f| render(f, &state))?;
+
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                match state.on_key(key) {
+                    Some(Picked::Device(target)) => break *target,
+                    Some(Picked::Cancel(e)) => Err(e)?,
+                    None => {}
+                }
+            }
+        }
+
+        let changed = match &mut watcher {
+            Some(w) => w.poll_changed()?,
+            None => last_refresh.elapsed() >= POLL_INTERVAL,
+        };
+        if changed {
+            debug!("Devices changed, listing them again");
+            state.update(list_devices()?);
+            last_refresh = Instant::now();
+        }
+    };
+    drop(tui);
+
+    let mounted = state.entry(&picked.devnode).is_some_and(|e| e.mounted);
+    eprintln!(
+        "Selected target disk: {}",
+        Description {
+            target: &picked,
+            mounted
+        }
+    );
+    Ok(picked)
+}
+
+/// Every device, along with whether it's mounted.
+fn list_devices() -> anyhow::Result<Vec<(WriteTarget, bool)>> {
+    let mut targets: Vec<WriteTarget> = enumerate_devices().collect();
+    targets.sort();
+
+    let mounts = MountTable::load()?;
+    Ok(targets
+        .into_iter()
+        .map(|mut t| {
+            // Only what udev knows, since this runs every time a device
+            // comes or goes.
+            if let Err(e) = t.load_partitions(&mounts, false) {
+                debug!(name = t.name, "Could not list partitions: {e}");
+            }
+            let mounted = mounts.mounts_of(&t).is_ok_and(|m| !m.is_empty());
+            (t, mounted)
+        })
+        .collect())
+}
+
+fn render(f: &mut Frame, state: &PickerState) {
+    let [list_area, help_area] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(f.size())[..]
+    else {
+        unreachable!()
+    };
+
+    let rows = state.rows();
+    let items: Vec<ListItem> = rows
+        .iter()
+        .map(|row| match row {
+            Cursor::Device(devnode) => {
+                let entry = state.entry(devnode).unwrap();
+                let description = Description {
+                    target: &entry.target,
+                    mounted: entry.mounted,
+                };
+                if entry.new {
+                    ListItem::new(format!("{description} [NEW]")).style(
+                        Style::default()
+                            .fg(Color::Green)
+                            .add_modifier(Modifier::BOLD),
+                    )
+                } else if entry.target.details.read_only == Some(true) {
+                    // It can be picked, but writing to it will be refused.
+                    ListItem::new(description.to_string())
+                        .style(Style::default().add_modifier(Modifier::DIM))
+                } else {
+                    ListItem::new(description.to_string())
+                }
+            }
+            Cursor::ToggleShowAll if state.show_all_disks => {
+                ListItem::new("<Only show removable disks>")
+            }
+            Cursor::ToggleShowAll => ListItem::new("<Show all disks, removable or not>"),
+        })
+        .collect();
+
+    let mut list_state =
+        ListState::default().with_selected(rows.iter().position(|r| *r == state.cursor));
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("Select target disk")
+                .borders(Borders::ALL),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    f.render_stateful_widget(list, list_area, &mut list_state);
+
+    let help = if state.show_all_disks {
+        Span::styled(
+            "Showing all disks. Proceed with caution!",
+            Style::default().fg(Color::Yellow),
+        )
+    } else {
+        Span::raw("Only displaying removable disks. Plug one in and it will show up here.")
+    };
+    f.render_widget(
+        Paragraph::new(ratatui::text::Line::from(vec![
+            help,
+            Span::raw(" [↑↓ to move, enter to select, esc to cancel]"),
+        ])),
+        help_area,
+    );
+}
+
+/// A one-line summary of a device.
+struct Description<'a> {
+    target: &'a WriteTarget,
+    mounted: bool,
+}
+
+impl fmt::Display for Description<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dev = self.target;
+        write!(f, "{} | ", dev.name)?;
+        if let Some(vendor) = &dev.details.vendor {
+            write!(f, "{vendor} ")?;
+        }
+        write!(
+            f,
+            "{} - {} ({}, {}, removable: {}",
+            dev.model, dev.size, dev.target_type, dev.details.transport, dev.removable
+        )?;
+        if let Some(serial) = &dev.details.serial {
+            write!(f, ", serial: {serial}")?;
+        }
+        if dev.details.read_only == Some(true) {
+            write!(f, ", READ-ONLY")?;
+        }
+        if dev.details.system_disk {
+            write!(f, ", SYSTEM DISK")?;
+        }
+        if self.mounted {
+            write!(f, ", MOUNTED")?;
+        }
+        if !dev.details.holders.is_empty() {
+            let holders = dev.details.holders.iter().map(|h| &h.holder);
+            write!(f, ", IN USE by {}", holders.format(", "))?;
+        }
+        let filesystems: Vec<String> = dev
+            .details
+            .partitions
+            .iter()
+            .filter_map(|p| Some(p.filesystem.as_ref()?.short_name()))
+            .collect();
+        if !filesystems.is_empty() {
+            write!(f, ", contains: {}", filesystems.join(", "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    target: WriteTarget,
+    mounted: bool,
+    /// Whether this was plugged in while the picker was open.
+    new: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cursor {
+    Device(PathBuf),
+    ToggleShowAll,
+}
+
+enum Picked {
+    Device(Box<WriteTarget>),
+    Cancel(InquireError),
+}
+
+#[derive(Debug)]
+struct PickerState {
+    show_all_disks: bool,
+    /// Every device, including the ones that aren't shown.
+    entries: Vec<Entry>,
+    /// Whether the devices have been listed yet. Everything in the first
+    /// listing was already plugged in, so none of it is new.
+    loaded: bool,
+    cursor: Cursor,
+}
+
+impl PickerState {
+    fn new(show_all_disks: bool) -> Self {
+        Self {
+            show_all_disks,
+            entries: vec![],
+            loaded: false,
+            cursor: Cursor::ToggleShowAll,
+        }
+    }
+
+    /// Replaces the list of devices. The first removable device that wasn't
+    /// there before gets selected, since it was most likely just plugged in
+    /// to be burned to.
+    fn update(&mut self, devices: Vec<(WriteTarget, bool)>) {
+        let old: HashSet<PathBuf> = self
+            .entries
+            .iter()
+            .map(|e| e.target.devnode.clone())
+            .collect();
+        let still_new: HashSet<PathBuf> = self
+            .entries
+            .iter()
+            .filter(|e| e.new)
+            .map(|e| e.target.devnode.clone())
+            .collect();
+
+        let mut inserted = None;
+        self.entries = devices
+            .into_iter()
+            .map(|(target, mounted)| {
+                let added = self.loaded && !old.contains(&target.devnode);
+                if added && inserted.is_none() && target.removable == Removable::Yes {
+                    inserted = Some(target.devnode.clone());
+                }
+                Entry {
+                    new: added || still_new.contains(&target.devnode),
+                    target,
+                    mounted,
+                }
+            })
+            .collect();
+        let first_load = !self.loaded;
+        self.loaded = true;
+
+        if first_load {
+            self.cursor = self.rows()[0].clone();
+        }
+        if let Some(devnode) = inserted {
+            self.cursor = Cursor::Device(devnode);
+        }
+        self.fix_cursor();
+    }
+
+    fn entry(&self, devnode: &PathBuf) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.target.devnode == *devnode)
+    }
+
+    /// What's on the screen, in order.
+    fn rows(&self) -> Vec<Cursor> {
+        self.entries
+            .iter()
+            .filter(|e| self.show_all_disks || e.target.removable == Removable::Yes)
+            .map(|e| Cursor::Device(e.target.devnode.clone()))
+            .chain([Cursor::ToggleShowAll])
+            .collect()
+    }
+
+    /// Moves the cursor back onto the list, if what it was on went away.
+    fn fix_cursor(&mut self) {
+        let rows = self.rows();
+        if !rows.contains(&self.cursor) {
+            self.cursor = rows[0].clone();
+        }
+    }
+
+    fn move_cursor(&mut self, up: bool) {
+        let rows = self.rows();
+        let i = rows.iter().position(|r| *r == self.cursor).unwrap_or(0);
+        let i = match up {
+            true => i.saturating_sub(1),
+            false => (i + 1).min(rows.len() - 1),
+        };
+        self.cursor = rows[i].clone();
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Option<Picked> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
+                return Some(Picked::Cancel(InquireError::OperationInterrupted))
+            }
+            (KeyCode::Esc | KeyCode::Char('q'), _) => {
+                return Some(Picked::Cancel(InquireError::OperationCanceled))
+            }
+            (KeyCode::Up | KeyCode::Char('k'), _) => self.move_cursor(true),
+            (KeyCode::Down | KeyCode::Char('j'), _) => self.move_cursor(false),
+            (KeyCode::Enter, _) => match &self.cursor {
+                Cursor::Device(devnode) => {
+                    let entry = self.entry(devnode)?;
+                    return Some(Picked::Device(Box::new(entry.target.clone())));
+                }
+                Cursor::ToggleShowAll => {
+                    self.show_all_disks = !self.show_all_disks;
+                    self.fix_cursor();
+                }
+            },
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent};
+
+    use crate::device::{DeviceDetails, Model, Removable, TargetSize, Type, WriteTarget};
+
+    use super::{Cursor, Picked, PickerState};
+
+    fn target(name: &str, removable: bool) -> (WriteTarget, bool) {
+        let target = WriteTarget {
+            name: name.into(),
+            devnode: format!("/dev/{name}").into(),
+            size: TargetSize::from(None),
+            model: Model::from(None),
+            removable: match removable {
+                true => Removable::Yes,
+                false => Removable::No,
+            },
+            target_type: Type::Disk,
+            details: DeviceDetails::default(),
+        };
+        (target, false)
+    }
+
+    fn cursor(name: &str) -> Cursor {
+        Cursor::Device(format!("/dev/{name}").into())
+    }
+
+    #[test]
+    fn inserted_device_is_selected_and_new() {
+        let mut state = PickerState::new(false);
+        state.update(vec![target("sda", false), target("sdb", true)]);
+        assert_eq!(state.cursor, cursor("sdb"));
+
+        state.update(vec![
+            target("sda", false),
+            target("sdb", true),
+            target("sdc", true),
+        ]);
+
+        assert_eq!(state.cursor, cursor("sdc"));
+        assert_eq!(
+            state.entries.iter().map(|e| e.new).collect::<Vec<_>>(),
+            vec![false, false, true]
+        );
+    }
+
+    #[test]
+    fn non_removable_devices_are_hidden_and_not_selected() {
+        let mut state = PickerState::new(false);
+        state.update(vec![target("sdb", true)]);
+
+        state.update(vec![target("sda", false), target("sdb", true)]);
+
+        assert_eq!(state.cursor, cursor("sdb"));
+        assert_eq!(state.rows(), vec![cursor("sdb"), Cursor::ToggleShowAll]);
+    }
+
+    #[test]
+    fn removed_device_moves_cursor() {
+        let mut state = PickerState::new(false);
+        state.update(vec![target("sdb", true), target("sdc", true)]);
+        state.on_key(KeyEvent::from(KeyCode::Down));
+        assert_eq!(state.cursor, cursor("sdc"));
+
+        state.update(vec![target("sdb", true)]);
+
+        assert_eq!(state.cursor, cursor("sdb"));
+    }
+
+    #[test]
+    fn show_all_and_pick() {
+        let mut state = PickerState::new(false);
+        state.update(vec![target("sda", false)]);
+        assert_eq!(state.cursor, Cursor::ToggleShowAll);
+
+        state.on_key(KeyEvent::from(KeyCode::Enter));
+        assert_eq!(state.rows(), vec![cursor("sda"), Cursor::ToggleShowAll]);
+        state.on_key(KeyEvent::from(KeyCode::Up));
+        let picked = state.on_key(KeyEvent::from(KeyCode::Enter));
+
+        let Some(Picked::Device(picked)) = picked else {
+            panic!("nothing was picked");
+        };
+        assert_eq!(picked.name, "sda");
+    }
+}
@@ -47,13 +47,16 @@ where
             terminal,
             handle: Some(handle),
             events: EventStream::new(),
-            state: State::initial(Instant::now(), &params, input_file_bytes),
+            state: State::initial(Instant::now(), params, input_file_bytes),
             log_paths,
         }
     }
 
     #[tracing::instrument(skip_all, level = "debug")]
-    pub async fn show(mut self) -> anyhow::Result<()> {
+    /// Runs the UI until the user quits. Returns the writer's final state, or
+    /// None if the user quit before the writer finished.
+    pub async fn show(mut self) -> anyhow::Result<Option<WriterState>> {
+        let mut finished = None;
         loop {
             match self.get_and_handle_events().await {
                 Ok(s) => self = s,
@@ -61,8 +64,11 @@ where
                     Quit => break,
                 },
             }
+            if finished.is_none() && self.state.child.is_finished() {
+                finished = Some(self.state.child.clone());
+            }
         }
-        Ok(())
+        Ok(finished)
     }
 
     #[tracing::instrument(skip_all, level = "trace")]
@@ -81,7 +87,7 @@ where
             self.handle = None;
         }
 
-        draw(&mut self.state, &mut self.terminal, &self.log_paths)?;
+        draw(&mut self.state, self.terminal, &self.log_paths)?;
         Ok(self)
     }
 }
@@ -180,7 +186,7 @@ pub fn draw(
 
     let info_table = WritingInfoTable {
         input_filename: &state.input_filename,
-        target_filename: &state.target_filename,
+        target: &state.target,
         state: &state.child,
     };
 
@@ -4,6 +4,7 @@ use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use tracing::info;
 
 use crate::{
+    device::WriteTarget,
     ui::{start::BeginParams, writer_tracking::WriterState},
     writer_process::ipc::StatusMessage,
 };
@@ -20,7 +21,7 @@ pub enum UIEvent {
 #[derive(Debug, Clone)]
 pub struct State {
     pub input_filename: String,
-    pub target_filename: String,
+    pub target: WriteTarget,
     pub child: WriterState,
     pub graph_state: SpeedChartState,
     pub quit_modal: Option<QuitModal>,
@@ -29,8 +30,8 @@ pub struct State {
 impl State {
     pub fn initial(now: Instant, params: &BeginParams, input_file_bytes: u64) -> Self {
         State {
-            input_filename: params.input_file.to_string_lossy().to_string(),
-            target_filename: params.target.devnode.to_string_lossy().to_string(),
+            input_filename: params.input.to_string(),
+            target: params.target.clone(),
             child: WriterState::initial(now, !params.compression.is_identity(), input_file_bytes),
             graph_state: SpeedChartState::default(),
             quit_modal: None,
@@ -13,7 +13,9 @@ use ratatui::{
     },
 };
 
+use crate::device::{Type, WriteTarget};
 use crate::ui::writer_tracking::WriterState;
+use crate::writer_process::ipc::InputHash;
 
 pub struct SpeedChart<'a> {
     pub state: &'a WriterState,
@@ -45,7 +47,6 @@ impl StatefulWidget for SpeedChart<'_> {
         let verify_speeds: Option<Vec<(f64, f64)>> = self.state.verify_hist().map(|verify_data| {
             verify_data
                 .speeds(window)
-                .into_iter()
                 .map(|(x, y)| (x + write_data.last_datapoint().0, y))
                 .collect()
         });
@@ -76,7 +77,7 @@ impl StatefulWidget for SpeedChart<'_> {
                 dataset_style
                     .name("Verify")
                     .style(Style::default().fg(Color::Blue))
-                    .data(&vdata),
+                    .data(vdata),
             );
         }
 
@@ -185,7 +186,7 @@ impl WriterProgressBar {
         }
     }
 
-    pub fn render(&self) -> Gauge {
+    pub fn render(&self) -> Gauge<'_> {
         if let Some(max) = self.display_total_bytes {
             Gauge::default()
                 .label(format!(
@@ -211,7 +212,7 @@ impl WriterProgressBar {
 
 pub struct WritingInfoTable<'a> {
     pub input_filename: &'a str,
-    pub target_filename: &'a str,
+    pub target: &'a WriteTarget,
     pub state: &'a WriterState,
 }
 
@@ -221,13 +222,57 @@ impl WritingInfoTable<'_> {
 
         let mut rows = vec![
             Row::new([Cell::from("Input"), Cell::from(self.input_filename)]),
-            Row::new([Cell::from("Output"), Cell::from(self.target_filename)]),
+            Row::new([
+                Cell::from("Output"),
+                Cell::from(self.target.devnode.to_string_lossy()),
+            ]),
             Row::new([
                 Cell::from("Avg. Write"),
                 Cell::from(format!("{}", wdata.total_avg_speed())),
             ]),
         ];
 
+        let details = &self.target.details;
+        if self.target.target_type != Type::File {
+            let model = match &details.vendor {
+                Some(vendor) => format!("{vendor} {}", self.target.model),
+                None => self.target.model.to_string(),
+            };
+            rows.push(Row::new([Cell::from("Model"), Cell::from(model)]));
+            rows.push(Row::new([
+                Cell::from("Transport"),
+                Cell::from(details.transport.to_string()),
+            ]));
+        }
+        if let Some(serial) = &details.serial {
+            rows.push(Row::new([
+                Cell::from("Serial"),
+                Cell::from(serial.as_str()),
+            ]));
+        }
+        if let Some(wwn) = &details.wwn {
+            rows.push(Row::new([Cell::from("WWN"), Cell::from(wwn.as_str())]));
+        }
+        if let Some(by_id) = &details.by_id {
+            rows.push(Row::new([
+                Cell::from("By ID"),
+                Cell::from(by_id.to_string_lossy()),
+            ]));
+        }
+
+        for h in &self.state.hashes().image {
+            rows.push(Row::new([
+                Cell::from(format!("Image {}", h.alg)),
+                Cell::from(base16::encode_lower(&h.hash)),
+            ]));
+        }
+        for InputHash { hash: h, hash_of } in &self.state.hashes().input {
+            rows.push(Row::new([
+                Cell::from(format!("Input {} ({hash_of})", h.alg)),
+                Cell::from(base16::encode_lower(&h.hash)),
+            ]));
+        }
+
         match &self.state {
             WriterState::Writing(st) => {
                 rows.push(Row::new([
@@ -261,6 +306,20 @@ impl WritingInfoTable<'_> {
             }
         }
 
+        for h in &self.state.hashes().device {
+            rows.push(Row::new([
+                Cell::from(format!("Disk {}", h.alg)),
+                Cell::from(base16::encode_lower(&h.hash)),
+            ]));
+            rows.push(Row::new([Cell::from("Disk SRI"), Cell::from(h.sri())]));
+        }
+        if self.state.hashes().implanted_md5_verified {
+            rows.push(Row::new([
+                Cell::from("ISO MD5"),
+                Cell::from("Implanted checksum matches"),
+            ]));
+        }
+
         Table::new(rows, [Constraint::Length(16), Constraint::Percentage(100)])
             .style(Style::default())
             .block(Block::default().title("Stats").borders(Borders::ALL))
@@ -1,4 +1,5 @@
 mod handle;
+#[allow(clippy::module_inception)]
 mod herder;
 mod socket;
 
@@ -1,4 +1,5 @@
 pub mod cli;
+mod device_picker;
 mod fancy_ui;
 mod herder;
 pub mod main;
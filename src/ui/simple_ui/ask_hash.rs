@@ -1,58 +1,171 @@
 use std::{
-    fs::File,
-    io::{BufReader, Seek},
+    io::{self, BufReader, Seek},
     path::Path,
     process::exit,
 };
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use bytesize::ByteSize;
 use indicatif::{ProgressBar, ProgressStyle};
-use inquire::{Select, Text};
+use inquire::{Confirm, Select, Text};
+use tracing::debug;
 
 use crate::{
+    checksum_file::{
+        alg_from_file_name, discover_checksum_files, find_entry, parse_checksum_file, ChecksumMatch,
+    },
     compression::{decompress, CompressionFormat},
-    hash::{parse_hash_input, FileHashInfo, HashAlg, Hashing},
+    hash::{parse_hash_input, Digests, HashAlg, HashRead, Hashing, MultiHasher},
+    hash_cache::{FileId, HashCache},
+    multipart::ImageParts,
+    signature::VerifiedSignature,
     ui::cli::{BurnArgs, HashArg, HashOf},
+    writer_process::ipc::ExpectedHash,
 };
 
+use super::ask_signature::verify_signature;
+
+/// The results of checking the input file before burning it.
+pub struct InputChecks {
+    /// The hash the writer should check the input against while writing, or
+    /// None if there is no hash.
+    pub input_hash: Option<ExpectedHash>,
+    pub signature: Option<VerifiedSignature>,
+    /// The input files, for caching the hashes the writer reports.
+    pub input_files: Option<Vec<FileId>>,
+}
+
 #[tracing::instrument(skip_all, fields(cf))]
-pub fn ask_hash(args: &BurnArgs, cf: CompressionFormat) -> anyhow::Result<Option<FileHashInfo>> {
-    let hash_params = match &args.hash {
-        HashArg::Skip => None,
-        HashArg::Ask => ask_hash_loop(cf)?,
-        HashArg::Hash { alg, expected_hash } => Some(BeginHashParams {
-            expected_hash: expected_hash.clone(),
-            alg: alg.clone(),
-            hasher_compression: ask_hasher_compression(cf, args.hash_of)?,
-        }),
+pub fn ask_hash(
+    args: &BurnArgs,
+    input: &ImageParts,
+    cf: CompressionFormat,
+) -> anyhow::Result<InputChecks> {
+    let hash_params = if let Some(hash_file) = &args.hash_file {
+        Some(hash_params_from_file(
+            hash_file,
+            input,
+            cf,
+            args.hash_of,
+            args.force,
+        )?)
+    } else {
+        match &args.hash[..] {
+            [HashArg::Skip] => None,
+            [HashArg::Ask] => match ask_discovered_hash_file(input, cf, args.hash_of)? {
+                Some(p) => Some(p),
+                None => ask_hash_loop(cf)?,
+            },
+            hashes => Some(BeginHashParams {
+                expected: hashes
+                    .iter()
+                    .map(|h| match h {
+                        HashArg::Hash {
+                            alg,
+                            expected_hash,
+                            assumed,
+                        } => {
+                            if *assumed {
+                                eprintln!(
+                                    "Assuming the hash is {alg}. If it isn't, give its algorithm \
+                                     too, like `{}-<hash>`.",
+                                    alg.same_length()[0].sri_alg()
+                                );
+                            }
+                            Ok((*alg, expected_hash.clone()))
+                        }
+                        _ => Err(anyhow!(
+                            "--hash ask and --hash skip cannot be combined with other hashes"
+                        )),
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+                hash_of: ask_hash_of(cf, args.hash_of)?,
+            }),
+        }
     };
 
+    // If the hash came from a checksum file, it must be verified before we
+    // can trust the hash in it. Only one the user named is taken to be what's
+    // signed, since a discovered one may not be.
+    let signature = verify_signature(args, input, args.hash_file.as_deref())?;
+
     let params = if let Some(p) = hash_params {
         p
     } else {
-        return Ok(None);
+        return Ok(InputChecks {
+            input_hash: None,
+            signature,
+            input_files: None,
+        });
     };
 
-    let hash_result = do_hashing(&args.input, &params)?;
+    let mut expected = params.into_expected_hash();
+    let mut cache = HashCache::load();
+    let files = FileId::of_parts(input)
+        .inspect_err(|e| debug!("Not using the hash cache: {e}"))
+        .ok();
 
-    if hash_result.file_hash == params.expected_hash {
-        eprintln!("Disk image verified successfully!");
-    } else {
-        eprintln!("Hash did not match!");
-        eprintln!(
-            "  Expected: {}",
-            base16::encode_lower(&params.expected_hash)
-        );
-        eprintln!(
-            "    Actual: {}",
-            base16::encode_lower(&hash_result.file_hash)
-        );
-        eprintln!("Your disk image may be corrupted!");
-        exit(-1);
+    let cached = match &files {
+        Some(files) if !args.rehash => cached_hashes(&cache, files, &expected, cf),
+        _ => None,
+    };
+    let (raw, compressed) = match cached {
+        Some(hashes) => {
+            eprintln!("Using the hash from a previous run. Pass --rehash to hash it again.");
+            hashes
+        }
+        None if args.hash_while_writing => {
+            eprintln!("The hash will be checked while writing.");
+            return Ok(InputChecks {
+                input_hash: Some(expected),
+                signature,
+                input_files: files,
+            });
+        }
+        None => {
+            let (raw, compressed) = do_hashing(input, &expected, cf)?;
+            if let Some(files) = &files {
+                for (hashes, hash_of) in [(&raw, HashOf::Raw), (&compressed, HashOf::Compressed)] {
+                    for (alg, digest) in hashes {
+                        cache.insert(files, *alg, hash_of, digest.clone());
+                    }
+                }
+                if let Err(e) = cache.save() {
+                    debug!("Failed to save the hash cache: {e}");
+                }
+            }
+            (raw, compressed)
+        }
+    };
+
+    let found = match expected.check(&raw, &compressed) {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(-1);
+        }
+    };
+
+    eprintln!("Disk image verified successfully!");
+    if expected.hash_of == HashOf::Auto {
+        for h in &found {
+            eprintln!("  The {} hash is of the {} file.", h.hash.alg, h.hash_of);
+        }
+        // Spare the writer from hashing both streams again.
+        if let Some(first) = found.first() {
+            if found.iter().all(|h| h.hash_of == first.hash_of) {
+                expected.hash_of = first.hash_of;
+            }
+        }
     }
 
-    Ok(Some(hash_result))
+    // Still pass it on, so the writer knows which algorithm to hash the
+    // device with.
+    Ok(InputChecks {
+        input_hash: Some(expected),
+        signature,
+        input_files: files,
+    })
 }
 
 #[tracing::instrument]
@@ -112,75 +225,261 @@ fn ask_hash_once(cf: CompressionFormat) -> anyhow::Result<BeginHashParams> {
         }
     };
 
-    let hasher_compression = ask_hasher_compression(cf, None)?;
+    let hash_of = ask_hash_of(cf, None)?;
 
     Ok(BeginHashParams {
-        expected_hash: hash,
-        alg,
-        hasher_compression,
+        expected: vec![(alg, hash)],
+        hash_of,
     })
 }
 
-#[tracing::instrument]
-fn ask_hasher_compression(
+/// Looks for checksum files next to the input file, and if one of them has
+/// an entry for it, offers to use that entry.
+#[tracing::instrument(skip(input))]
+fn ask_discovered_hash_file(
+    input: &ImageParts,
+    cf: CompressionFormat,
+    hash_of: Option<HashOf>,
+) -> anyhow::Result<Option<BeginHashParams>> {
+    let joined = input.joined_path();
+    let dir = match joined.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+
+    let names = candidate_names(input, cf);
+    let name_refs: Vec<&str> = names.iter().map(String::as_str).collect();
+    for hash_file in discover_checksum_files(dir, &name_refs) {
+        let found = match lookup_hash_file(&hash_file, input, cf) {
+            Ok(f) => f,
+            Err(e) => {
+                debug!(?hash_file, "Skipping checksum file: {e}");
+                continue;
+            }
+        };
+
+        let ans = Confirm::new(&format!(
+            "Found {} with an entry for this file. Use it?",
+            file_name_of(&hash_file)
+        ))
+        .with_default(true)
+        .prompt_skippable()?;
+
+        if ans == Some(true) {
+            return Ok(Some(params_from_match(found, cf, hash_of, false)?));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Looks up the input file in a checksum file.
+#[tracing::instrument(skip(input))]
+fn hash_params_from_file(
+    hash_file: &Path,
+    input: &ImageParts,
+    cf: CompressionFormat,
+    hash_of: Option<HashOf>,
+    force: bool,
+) -> anyhow::Result<BeginHashParams> {
+    let found = lookup_hash_file(hash_file, input, cf)?;
+    eprintln!(
+        "Found entry for {} in {}",
+        found.entry_name,
+        hash_file.to_string_lossy()
+    );
+
+    params_from_match(found, cf, hash_of, force)
+}
+
+/// The names the input may be listed under in a checksum file: either the
+/// file exactly as we have it, or, if it's compressed, the image inside of it.
+fn candidate_names(input: &ImageParts, cf: CompressionFormat) -> Vec<String> {
+    let joined = input.joined_path();
+    let mut candidates = vec![file_name_of(&joined)];
+    if !cf.is_identity() {
+        candidates.push(file_name_of(joined.with_extension("")));
+    }
+    candidates
+}
+
+fn lookup_hash_file(
+    hash_file: &Path,
+    input: &ImageParts,
+    cf: CompressionFormat,
+) -> anyhow::Result<ChecksumMatch> {
+    let contents = std::fs::read_to_string(hash_file).with_context(|| {
+        format!(
+            "Failed to read checksum file {}",
+            hash_file.to_string_lossy()
+        )
+    })?;
+    let entries = parse_checksum_file(&contents);
+
+    let candidates = candidate_names(input, cf);
+    let candidate_refs: Vec<&str> = candidates.iter().map(String::as_str).collect();
+
+    Ok(find_entry(
+        &entries,
+        &candidate_refs,
+        alg_from_file_name(hash_file),
+    )?)
+}
+
+/// With `force`, nothing is asked. A hash that could be of several
+/// algorithms is assumed to be SHA-2, like a bare hash given with `--hash`.
+fn params_from_match(
+    found: ChecksumMatch,
     cf: CompressionFormat,
     hash_of: Option<HashOf>,
-) -> anyhow::Result<CompressionFormat> {
+    force: bool,
+) -> anyhow::Result<BeginHashParams> {
+    let alg = match &found.algs[..] {
+        &[only_alg] => {
+            eprintln!("Detected {}", only_alg);
+            only_alg
+        }
+        &[first, second, ..] if force => {
+            eprintln!(
+                "Assuming the hash is {first}. If it isn't, give it with --hash instead, \
+                 like `{}-<hash>`.",
+                second.sri_alg()
+            );
+            first
+        }
+        multiple => Select::new("Which algorithm is it?", multiple.into()).prompt()?,
+    };
+
+    let hash_of = match (hash_of, found.candidate) {
+        _ if cf.is_identity() => HashOf::Raw,
+        (Some(_), _) => ask_hash_of(cf, hash_of)?,
+        (None, 0) => HashOf::Compressed,
+        (None, _) => HashOf::Raw,
+    };
+
+    Ok(BeginHashParams {
+        expected: vec![(alg, found.expected_hash)],
+        hash_of,
+    })
+}
+
+pub(super) fn file_name_of(path: impl AsRef<Path>) -> String {
+    path.as_ref()
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Figures out which form of the input the hash is of. Uncompressed inputs
+/// only have one form, which is always treated as raw.
+#[tracing::instrument]
+fn ask_hash_of(cf: CompressionFormat, hash_of: Option<HashOf>) -> anyhow::Result<HashOf> {
     if cf.is_identity() {
-        return Ok(cf);
+        return Ok(HashOf::Raw);
     }
 
-    let ans = hash_of.map(Ok).unwrap_or_else(|| {
+    Ok(hash_of.map(Ok).unwrap_or_else(|| {
         Select::new(
             "Is the hash calculated from the raw file or the compressed file?",
-            vec![HashOf::Raw, HashOf::Compressed],
+            vec![HashOf::Auto, HashOf::Raw, HashOf::Compressed],
         )
+        .with_help_message("If you're not sure, pick the first option to check both")
         .prompt()
-    })?;
+    })?)
+}
 
-    Ok(match ans {
-        HashOf::Raw => cf,
-        HashOf::Compressed => CompressionFormat::Identity,
-    })
+/// Looks up the hashes of every stream that [do_hashing] would hash.
+fn cached_hashes(
+    cache: &HashCache,
+    files: &[FileId],
+    expected: &ExpectedHash,
+    cf: CompressionFormat,
+) -> Option<(Digests, Digests)> {
+    let (hash_raw, hash_compressed) = expected.hashed_streams(cf);
+    let lookup = |enabled: bool, hash_of: HashOf| {
+        if enabled {
+            cache.get_all(files, expected.algs(), hash_of)
+        } else {
+            Some(vec![])
+        }
+    };
+
+    Some((
+        lookup(hash_raw, HashOf::Raw)?,
+        lookup(hash_compressed, HashOf::Compressed)?,
+    ))
 }
 
-#[tracing::instrument(skip_all, fields(path))]
-fn do_hashing(path: &Path, params: &BeginHashParams) -> anyhow::Result<FileHashInfo> {
-    let mut file = File::open(path)?;
+/// Hashes the input in a separate pass. If the hash may be of either the raw
+/// or the compressed file, both are hashed at once.
+///
+/// Returns the hashes of the raw and the compressed file, in that order.
+/// Either is empty if it wasn't needed.
+#[tracing::instrument(skip_all, fields(input))]
+fn do_hashing(
+    input: &ImageParts,
+    expected: &ExpectedHash,
+    cf: CompressionFormat,
+) -> anyhow::Result<(Digests, Digests)> {
+    let file = input.open()?;
 
     // Calculate total file size
-    let file_size = file.seek(std::io::SeekFrom::End(0))?;
-    file.seek(std::io::SeekFrom::Start(0))?;
+    let file_size = file.len();
 
     let progress_bar = ProgressBar::new(file_size);
     progress_bar.set_style(
         ProgressStyle::with_template("{bytes:>10} / {total_bytes:<10} {wide_bar}").unwrap(),
     );
 
-    let decompress = decompress(params.hasher_compression, BufReader::new(file))
+    let (hash_raw, hash_compressed) = expected.hashed_streams(cf);
+    let mut compressed_hasher = MultiHasher::new(expected.algs().filter(|_| hash_compressed));
+    let file = HashRead::new(file, Some(&mut compressed_hasher));
+
+    let cf = if hash_raw {
+        cf
+    } else {
+        CompressionFormat::Identity
+    };
+    let decompress = decompress(cf, BufReader::new(file))
         .context("Failed to open input file with decompressor")?;
 
+    let raw_algs: Vec<HashAlg> = expected.algs().filter(|_| hash_raw).collect();
     let mut hashing = Hashing::new(
-        params.alg,
+        &raw_algs,
         decompress,
         ByteSize::kib(512).as_u64() as usize, // TODO
     );
-    loop {
+    'outer: loop {
         for _ in 0..32 {
-            match hashing.next() {
-                Some(_) => {}
-                None => return Ok(hashing.finalize()?),
+            if hashing.next().is_none() {
+                break 'outer;
             }
         }
         progress_bar.set_position(hashing.get_reader_mut().get_mut().stream_position()?);
     }
+
+    // Decompressors may stop before the end of the file (i.e. if there is
+    // trailing padding), but the hash covers all of it.
+    io::copy(hashing.get_reader_mut().get_mut(), &mut io::sink())?;
+    let raw = hashing.finalize()?;
+
+    Ok((raw.file_hashes, compressed_hasher.finalize()))
 }
 
 #[derive(Debug)]
 struct BeginHashParams {
-    expected_hash: Vec<u8>,
-    alg: HashAlg,
-    hasher_compression: CompressionFormat,
+    /// The hashes to check. They're all computed in the same pass.
+    expected: Vec<(HashAlg, Vec<u8>)>,
+    hash_of: HashOf,
+}
+
+impl BeginHashParams {
+    fn into_expected_hash(self) -> ExpectedHash {
+        ExpectedHash {
+            expected: self.expected,
+            hash_of: self.hash_of,
+        }
+    }
 }
 
 /// A signaling error for the outer loop.
@@ -0,0 +1,94 @@
+use std::{
+    io::{self, BufReader, Read},
+    process::exit,
+};
+
+use anyhow::Context;
+use indicatif::{ProgressBar, ProgressStyle};
+use inquire::Confirm;
+
+use crate::{
+    compression::{decompress, CompressionFormat},
+    hash::HashRead,
+    isomd5::{ImplantedMd5, ImplantedMd5Hasher},
+    multipart::ImageParts,
+    ui::cli::{BurnArgs, CheckIsoMd5},
+};
+
+/// Looks for a checksum implanted in the input ISO, and offers to check it
+/// before burning. Returns the checksum, so that the disk can be checked
+/// against it too.
+///
+/// Exits the process if the checksum does not match.
+#[tracing::instrument(skip_all, fields(cf))]
+pub fn ask_implanted_md5(
+    args: &BurnArgs,
+    input: &ImageParts,
+    cf: CompressionFormat,
+) -> anyhow::Result<Option<ImplantedMd5>> {
+    if args.check_iso_md5 == CheckIsoMd5::Never {
+        return Ok(None);
+    }
+
+    let file = BufReader::new(input.open()?);
+    let decompressed =
+        decompress(cf, file).context("Failed to open input file with decompressor")?;
+    let Some(implanted) = ImplantedMd5::read(decompressed)? else {
+        return Ok(None);
+    };
+
+    eprintln!(
+        "This ISO has an implanted checksum: {}",
+        base16::encode_lower(&implanted.md5)
+    );
+    let check_now = match args.check_iso_md5 {
+        CheckIsoMd5::Always => true,
+        CheckIsoMd5::Ask if args.force => false,
+        _ => Confirm::new("Check it before burning?")
+            .with_help_message("The disk will be checked against it after burning either way")
+            .with_default(true)
+            .prompt()?,
+    };
+    if check_now {
+        check_input(input, cf, &implanted)?;
+    }
+
+    Ok(Some(implanted))
+}
+
+fn check_input(
+    input: &ImageParts,
+    cf: CompressionFormat,
+    implanted: &ImplantedMd5,
+) -> anyhow::Result<()> {
+    let file = input.open()?;
+    let progress_bar = ProgressBar::new(file.len()).with_style(
+        ProgressStyle::with_template("{bytes:>10} / {total_bytes:<10} {wide_bar}").unwrap(),
+    );
+    let decompressed = decompress(cf, BufReader::new(progress_bar.wrap_read(file)))
+        .context("Failed to open input file with decompressor")?;
+
+    let mut hasher = ImplantedMd5Hasher::new(implanted);
+    io::copy(
+        &mut HashRead::new(decompressed, Some(&mut hasher)).take(implanted.len),
+        &mut io::sink(),
+    )?;
+    progress_bar.finish_and_clear();
+
+    match hasher.finalize() {
+        Some(actual) if actual == implanted.md5 => {
+            eprintln!("The implanted checksum matches!");
+            Ok(())
+        }
+        actual => {
+            eprintln!("The implanted checksum did not match!");
+            eprintln!("  Expected: {}", base16::encode_lower(&implanted.md5));
+            match actual {
+                Some(actual) => eprintln!("    Actual: {}", base16::encode_lower(&actual)),
+                None => eprintln!("  The ISO is shorter than the checksum says it should be."),
+            }
+            eprintln!("Your disk image may be corrupted!");
+            exit(-1);
+        }
+    }
+}
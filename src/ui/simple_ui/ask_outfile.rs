@@ -1,25 +1,38 @@
-use std::fmt;
+use std::process::exit;
 
+use bytesize::ByteSize;
 use inquire::{Confirm, InquireError, Select};
 use tracing::debug;
 
 use crate::{
+    allowlist::{self, user_allowlists},
     compression::{CompressionArg, CompressionFormat, AVAILABLE_FORMATS},
-    device::{enumerate_devices, Removable, WriteTarget},
-    ui::{cli::BurnArgs, start::BeginParams},
+    device::{enumerate_devices, Type, WriteTarget},
+    image_size::{gpt_disk_size, ImageSize},
+    mounts::{Mount, MountTable},
+    multipart::ImageParts,
+    target_match::TargetMatch,
+    ui::{
+        cli::{BurnArgs, IfMounted},
+        device_picker::pick_device,
+        start::BeginParams,
+    },
 };
 
 #[tracing::instrument(skip_all)]
-pub fn ask_compression(args: &BurnArgs) -> anyhow::Result<CompressionFormat> {
+pub fn ask_compression(args: &BurnArgs, input: &ImageParts) -> anyhow::Result<CompressionFormat> {
     let cf = match args.compression {
         CompressionArg::Auto | CompressionArg::Ask => {
-            CompressionFormat::detect_from_path(&args.input)
+            CompressionFormat::detect_from_path(input.joined_path())
         }
         other => other.associated_format(),
     };
 
     if let Some(cf) = cf {
         eprintln!("Input file: {}", args.input.to_string_lossy());
+        if input.is_split() {
+            eprintln!("Detected split image with {} parts", input.parts().len());
+        }
         eprintln!("Detected compression format: {}", cf);
 
         if args.force || args.compression != CompressionArg::Ask {
@@ -47,92 +60,191 @@ pub fn ask_compression(args: &BurnArgs) -> anyhow::Result<CompressionFormat> {
 
 #[tracing::instrument(skip_all)]
 pub fn ask_outfile(args: &BurnArgs) -> anyhow::Result<WriteTarget> {
-    let mut show_all_disks = args.show_all_disks;
-
-    loop {
-        debug!(show_all_disks, "Beginning loop");
-
-        let targets = enumerate_options(show_all_disks)?;
-
-        let ans = Select::new("Select target disk", targets)
-            .with_help_message(if show_all_disks {
-                "Showing all disks. Proceed with caution!"
-            } else {
-                "Only displaying removable disks."
-            })
-            .prompt()?;
-
-        let dev = match ans {
-            ListOption::Device(dev) => dev,
-            ListOption::RetryWithShowAll(sa) => {
-                show_all_disks = sa;
-                continue;
-            }
-            ListOption::Refresh => {
-                continue;
-            }
-        };
-        return Ok(dev);
+    pick_device(args.show_all_disks)
+}
+
+/// Finds the one device matching `--target-match`, or exits if there isn't
+/// exactly one.
+#[tracing::instrument(skip_all)]
+pub fn find_target(target_match: &TargetMatch) -> WriteTarget {
+    match target_match.resolve(enumerate_devices()) {
+        Ok(target) => {
+            eprintln!(
+                "{} matched {}",
+                target_match,
+                target.devnode.to_string_lossy()
+            );
+            target
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            exit(-1);
+        }
     }
 }
 
+/// Exits if there is an allowlist, and the target isn't in it. Not even
+/// `--force` gets around this.
 #[tracing::instrument(skip_all)]
-pub fn confirm_write(args: &BurnArgs, begin_params: &BeginParams) -> Result<bool, InquireError> {
-    if args.force {
-        debug!("Skipping confirm because of --force");
-        Ok(true)
-    } else {
-        println!("{}", begin_params);
+pub fn refuse_disallowed_target(target: &WriteTarget) {
+    if let Err(e) = allowlist::check(&target.devnode, &user_allowlists()) {
+        eprintln!("{e}");
+        eprintln!("Refusing to write to a target outside the allowlist!");
+        exit(-1);
+    }
+}
 
-        Confirm::new("Is this okay?")
-            .with_help_message("THIS ACTION WILL DESTROY ALL DATA ON THIS DEVICE!!!")
-            .with_default(false)
-            .prompt()
+/// Exits if the target is read-only, since writing to it can only fail.
+#[tracing::instrument(skip_all)]
+pub fn refuse_read_only(target: &WriteTarget) {
+    if target.details.read_only != Some(true) {
+        return;
     }
+    eprintln!(
+        "Refusing to write to {}, since it is read-only.",
+        target.devnode.to_string_lossy()
+    );
+    eprintln!("If it's an SD card, check that the lock switch on its side is not set.");
+    exit(-1);
 }
 
-enum ListOption {
-    Device(WriteTarget),
-    Refresh,
-    RetryWithShowAll(bool),
+/// Exits if the target is the disk that the running system is on, unless
+/// the user went out of their way to allow it.
+#[tracing::instrument(skip_all)]
+pub fn refuse_system_disk(args: &BurnArgs, target: &WriteTarget) {
+    if !target.details.system_disk {
+        return;
+    }
+    if args.i_really_want_to_overwrite_my_system_disk {
+        eprintln!(
+            "WARNING: {} is the disk that the running system is on!",
+            target.devnode.to_string_lossy()
+        );
+        return;
+    }
+
+    eprintln!(
+        "Refusing to write to {}, since the running system is on it.",
+        target.devnode.to_string_lossy()
+    );
+    exit(-1);
 }
 
-impl fmt::Display for ListOption {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ListOption::Device(dev) => {
-                write!(
-                    f,
-                    "{} | {} - {} ({}, removable: {})",
-                    dev.name, dev.model, dev.size, dev.target_type, dev.removable
-                )?;
-            }
-            ListOption::RetryWithShowAll(true) => {
-                write!(f, "<Show all disks, removable or not>")?;
-            }
-            ListOption::RetryWithShowAll(false) => {
-                write!(f, "<Only show removable disks>")?;
-            }
-            ListOption::Refresh => {
-                write!(f, "<Refresh devices>")?;
-            }
-        }
-        Ok(())
+/// Checks whether anything on the target is mounted, and if so, whether to
+/// unmount it. Returns what to unmount before writing.
+#[tracing::instrument(skip_all)]
+pub fn ask_unmount(args: &BurnArgs, target: &WriteTarget) -> anyhow::Result<Vec<Mount>> {
+    if args.if_mounted == IfMounted::Ignore {
+        return Ok(vec![]);
+    }
+
+    let mounts = MountTable::load()?.mounts_of(target)?;
+    if mounts.is_empty() {
+        return Ok(mounts);
+    }
+
+    eprintln!("{} is in use:", target.devnode.to_string_lossy());
+    for mount in &mounts {
+        eprintln!("  {mount}");
     }
+    let unmount = match args.if_mounted {
+        IfMounted::Unmount => true,
+        _ if args.force => false,
+        _ => Confirm::new("Unmount before writing?")
+            .with_help_message("Writing over a mounted disk will corrupt it")
+            .with_default(false)
+            .prompt()?,
+    };
+    if !unmount {
+        eprintln!(
+            "Refusing to write to a mounted disk. Unmount it, or pass --if-mounted unmount or \
+             --if-mounted ignore."
+        );
+        exit(-1);
+    }
+
+    Ok(mounts)
 }
 
-#[tracing::instrument]
-fn enumerate_options(show_all_disks: bool) -> anyhow::Result<Vec<ListOption>> {
-    let mut burn_targets: Vec<WriteTarget> = enumerate_devices()
-        .filter(|d| show_all_disks || d.removable == Removable::Yes)
-        .collect();
+/// Exits if the image won't fit on the target, and warns if it might not.
+#[tracing::instrument(skip_all)]
+pub fn check_fits(params: &BeginParams) -> anyhow::Result<()> {
+    let target = &params.target;
+    let Some(target_size) = target.size.bytes() else {
+        return Ok(());
+    };
+    if target.target_type == Type::File {
+        return Ok(());
+    }
+    let target_name = target.devnode.to_string_lossy();
+
+    let image_size = ImageSize::of(&params.input, params.compression)?;
+    debug!(?image_size, target_size, "Checking that the image fits");
+    match image_size {
+        ImageSize::Exact(n) if n > target_size => {
+            eprintln!(
+                "The image is {image_size}, which is larger than {target_name} ({}).",
+                target.size
+            );
+            exit(-1);
+        }
+        ImageSize::Estimated(n) if n > target_size => {
+            eprintln!(
+                "WARNING: The image is {image_size} once decompressed, which is larger than \
+                 {target_name} ({}). The write may fail partway through.",
+                target.size
+            );
+        }
+        _ => {}
+    }
+
+    if let Some(gpt_size) = gpt_disk_size(&params.input, params.compression)? {
+        if gpt_size > target_size {
+            eprintln!(
+                "The image's GPT partition table is for a disk of {}, which is larger than \
+                 {target_name} ({}). Its backup header would be past the end of the disk.",
+                ByteSize::b(gpt_size),
+                target.size
+            );
+            exit(-1);
+        }
+    }
+
+    Ok(())
+}
 
-    burn_targets.sort();
+#[tracing::instrument(skip_all)]
+pub fn confirm_write(args: &BurnArgs, begin_params: &BeginParams) -> Result<bool, InquireError> {
+    let holders = &begin_params.target.details.holders;
 
-    let options = burn_targets.into_iter().map(ListOption::Device).chain([
-        ListOption::Refresh,
-        ListOption::RetryWithShowAll(!show_all_disks),
-    ]);
+    if args.force {
+        if !holders.is_empty() && args.if_mounted != IfMounted::Ignore {
+            eprintln!(
+                "{} is in use:",
+                begin_params.target.devnode.to_string_lossy()
+            );
+            for holder in holders {
+                eprintln!("  {holder}");
+            }
+            eprintln!(
+                "Refusing to write to it. Stop whatever is using it, or pass --if-mounted ignore."
+            );
+            return Ok(false);
+        }
+        debug!("Skipping confirm because of --force");
+        Ok(true)
+    } else {
+        println!("{}", begin_params);
 
-    Ok(options.collect())
+        let help = if holders.is_empty() {
+            "THIS ACTION WILL DESTROY ALL DATA ON THIS DEVICE!!!"
+        } else {
+            "THIS DEVICE IS PART OF A RAID ARRAY, LVM VOLUME OR ENCRYPTED VOLUME THAT IS STILL \
+             IN USE!!! WRITING TO IT WILL DESTROY THAT TOO!!!"
+        };
+        Confirm::new("Is this okay?")
+            .with_help_message(help)
+            .with_default(false)
+            .prompt()
+    }
 }
@@ -0,0 +1,109 @@
+use std::{fs::File, path::Path, process::exit};
+
+use anyhow::Context;
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::{
+    multipart::ImageParts,
+    signature::{minisign, openpgp::Keyring, signify, TrustedKey, VerifiedSignature},
+    ui::cli::BurnArgs,
+};
+
+use super::ask_hash::file_name_of;
+
+/// Verifies `--signature` against the trusted key, if they were given. The
+/// signature is checked against `checksum_file` if there is one, and against
+/// the input file otherwise.
+///
+/// Exits the process if the signature does not verify.
+#[tracing::instrument(skip(args, input))]
+pub fn verify_signature(
+    args: &BurnArgs,
+    input: &ImageParts,
+    checksum_file: Option<&Path>,
+) -> anyhow::Result<Option<VerifiedSignature>> {
+    let Some(signature_path) = &args.signature else {
+        return Ok(None);
+    };
+    let key = load_trusted_key(args)?;
+    let signature = std::fs::read(signature_path).with_context(|| {
+        format!(
+            "Failed to read signature {}",
+            signature_path.to_string_lossy()
+        )
+    })?;
+
+    let (signed_file, result) = match checksum_file {
+        Some(path) => {
+            let file = File::open(path).with_context(|| {
+                format!("Failed to open checksum file {}", path.to_string_lossy())
+            })?;
+            eprintln!("Verifying signature of {}...", file_name_of(path));
+            (file_name_of(path), key.verify_detached(&signature, file))
+        }
+        None => {
+            eprintln!(
+                "Verifying signature of {}...",
+                file_name_of(input.joined_path())
+            );
+            let file = input.open()?;
+            let progress_bar = ProgressBar::new(file.len()).with_style(
+                ProgressStyle::with_template("{bytes:>10} / {total_bytes:<10} {wide_bar}").unwrap(),
+            );
+            let result = key.verify_detached(&signature, progress_bar.wrap_read(file));
+            progress_bar.finish_and_clear();
+            (file_name_of(input.joined_path()), result)
+        }
+    };
+
+    match result {
+        Ok(signer) => {
+            let verified = VerifiedSignature {
+                scheme: key.scheme(),
+                signed_file,
+                signer,
+            };
+            eprintln!("{verified}");
+            Ok(Some(verified))
+        }
+        Err(e) => {
+            eprintln!("Signature verification of {signed_file} failed!");
+            eprintln!("  {e}");
+            if checksum_file.is_none() {
+                eprintln!("If it's a signature of a checksum file, pass that with --hash-file.");
+            }
+            exit(-1);
+        }
+    }
+}
+
+fn load_trusted_key(args: &BurnArgs) -> anyhow::Result<TrustedKey> {
+    let read = |path: &Path| {
+        std::fs::read(path)
+            .with_context(|| format!("Failed to read key {}", path.to_string_lossy()))
+    };
+
+    if let Some(path) = &args.keyring {
+        let keyring = Keyring::parse(&read(path)?)
+            .with_context(|| format!("Failed to load keyring {}", path.to_string_lossy()))?;
+        Ok(TrustedKey::OpenPgp(keyring))
+    } else if let Some(key) = &args.minisign_pubkey {
+        // This may be either a path to a key file, or the key itself.
+        let path = Path::new(key);
+        let data = if path.exists() {
+            read(path)?
+        } else {
+            key.as_bytes().to_vec()
+        };
+        Ok(TrustedKey::Minisign(
+            minisign::parse_public_key(&data).context("Failed to load minisign public key")?,
+        ))
+    } else if let Some(path) = &args.signify_pubkey {
+        Ok(TrustedKey::Signify(
+            signify::PublicKey::parse(&read(path)?).context("Failed to load signify public key")?,
+        ))
+    } else {
+        // clap ensures that a key is given alongside --signature.
+        unreachable!("--signature was given without a key")
+    }
+}
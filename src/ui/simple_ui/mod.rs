@@ -4,37 +4,77 @@
 //! As pretty as ratatui looks, sometimes you can't use a full-featured terminal.
 //! This is what this module is for.
 
-use std::time::Instant;
+use std::{process::exit, time::Instant};
 
+use bytesize::ByteSize;
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
 
 use crate::compression::CompressionFormat;
 use crate::device::WriteTarget;
+use crate::mounts::MountTable;
 use crate::ui::writer_tracking::WriterState;
+use crate::writer_process::ipc::InputHash;
 
 use self::ask_hash::ask_hash;
+use self::ask_hash::InputChecks;
+use self::ask_isomd5::ask_implanted_md5;
 use self::ask_outfile::ask_compression;
 use self::ask_outfile::ask_outfile;
+use self::ask_outfile::ask_unmount;
+use self::ask_outfile::check_fits;
 use self::ask_outfile::confirm_write;
+use self::ask_outfile::find_target;
+use self::ask_outfile::refuse_disallowed_target;
+use self::ask_outfile::refuse_read_only;
+use self::ask_outfile::refuse_system_disk;
 
 use super::cli::BurnArgs;
 use super::herder::WriterHandle;
 use super::start::BeginParams;
 
 mod ask_hash;
+mod ask_isomd5;
 mod ask_outfile;
+mod ask_signature;
 
 /// Returns the [BeginParams] if the user confirms, and None if the user doesn't.
 #[tracing::instrument(skip_all)]
 pub fn do_setup_wizard(args: &BurnArgs) -> Result<Option<BeginParams>, anyhow::Error> {
-    let compression = ask_compression(args)?;
-    let _hash_info = ask_hash(args, compression)?;
-    let target = match &args.out {
-        Some(f) => WriteTarget::try_from(f.as_ref())?,
-        None => ask_outfile(args)?,
+    let input = args.image_parts();
+    let compression = ask_compression(args, &input)?;
+    let InputChecks {
+        input_hash,
+        signature,
+        input_files,
+    } = ask_hash(args, &input, compression)?;
+    let implanted_md5 = ask_implanted_md5(args, &input, compression)?;
+    let mut target = match (&args.out, &args.target_match) {
+        (Some(f), _) => WriteTarget::try_from(f.as_ref())?,
+        (None, Some(m)) => find_target(m),
+        (None, None) => ask_outfile(args)?,
     };
-    let begin_params = BeginParams::new(args.input.clone(), compression, target)?;
+    refuse_disallowed_target(&target);
+    refuse_system_disk(args, &target);
+    refuse_read_only(&target);
+    target.load_partitions(&MountTable::load()?, true)?;
+    let unmount = ask_unmount(args, &target)?;
+    let begin_params = BeginParams {
+        input_file_size: ByteSize::b(input.total_size()?),
+        input,
+        compression,
+        target,
+        verify_strategy: args.verify_strategy(compression),
+        input_hash,
+        digests: args.digest.clone(),
+        signature,
+        implanted_md5,
+        unmount,
+        allow_system_disk: args.i_really_want_to_overwrite_my_system_disk,
+        sync_strategy: args.sync,
+        input_files,
+    };
+    check_fits(&begin_params)?;
     if !confirm_write(args, &begin_params)? {
         eprintln!("Aborting.");
         return Ok(None);
@@ -46,7 +86,7 @@ pub fn do_setup_wizard(args: &BurnArgs) -> Result<Option<BeginParams>, anyhow::E
 pub async fn run_simple_burning_ui(
     mut handle: WriterHandle,
     cf: CompressionFormat,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<WriterState> {
     let input_file_bytes = handle.initial_info().input_file_bytes;
     let write_progress = ProgressBar::new(100).with_message("Burning").with_style(
         ProgressStyle::with_template(
@@ -76,6 +116,32 @@ pub async fn run_simple_burning_ui(
             WriterState::Finished { .. } => break,
         }
     }
+
+    let hashes = child_state.hashes();
+    for h in &hashes.image {
+        println!("Image {}: {}", h.alg, base16::encode_lower(&h.hash));
+    }
+    for InputHash { hash: h, hash_of } in &hashes.input {
+        println!(
+            "Input {} ({hash_of}): {}",
+            h.alg,
+            base16::encode_lower(&h.hash)
+        );
+    }
+    for h in &hashes.device {
+        println!("Disk {}: {}", h.alg, base16::encode_lower(&h.hash));
+        println!("Disk SRI: {}", h.sri());
+    }
+    if hashes.implanted_md5_verified {
+        println!("Disk matches the checksum implanted in the ISO");
+    }
+    if let WriterState::Finished {
+        error: Some(error), ..
+    } = &child_state
+    {
+        eprintln!("{error}");
+        exit(-1);
+    }
     println!("Done!");
-    Ok(())
+    Ok(child_state)
 }
@@ -1,4 +1,4 @@
-use std::{fmt::Display, fs::File, path::PathBuf, sync::Arc};
+use std::{fmt::Display, sync::Arc};
 
 use bytesize::ByteSize;
 use inquire::Confirm;
@@ -6,48 +6,67 @@ use tracing::debug;
 
 use crate::{
     compression::CompressionFormat,
-    device::WriteTarget,
+    device::{Type, WriteTarget},
+    hash::HashAlg,
+    hash_cache::{FileId, HashCache},
+    isomd5::ImplantedMd5,
     logging::LogPaths,
+    mounts::Mount,
+    multipart::ImageParts,
+    signature::VerifiedSignature,
     ui::{
-        cli::{Interactive, UseSudo},
+        cli::{Interactive, SyncStrategy, UseSudo},
         fancy_ui::FancyUI,
         herder::{Herder, StartWriterError, WriterHandle},
         simple_ui::run_simple_burning_ui,
         utils::TUICapture,
+        writer_tracking::WriterState,
+    },
+    writer_process::ipc::{
+        ErrorType, ExpectedHash, InputHash, VerifyStrategy, WriterProcessConfig,
     },
-    writer_process::ipc::{ErrorType, WriterProcessConfig},
 };
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BeginParams {
-    pub input_file: PathBuf,
+    pub input: ImageParts,
     pub input_file_size: ByteSize,
     pub compression: CompressionFormat,
     pub target: WriteTarget,
+    pub verify_strategy: VerifyStrategy,
+    pub input_hash: Option<ExpectedHash>,
+    /// Digests of the image to report, besides any that are checked.
+    pub digests: Vec<HashAlg>,
+    pub signature: Option<VerifiedSignature>,
+    pub implanted_md5: Option<ImplantedMd5>,
+    pub unmount: Vec<Mount>,
+    /// Whether the user passed --i-really-want-to-overwrite-my-system-disk.
+    pub allow_system_disk: bool,
+    pub sync_strategy: SyncStrategy,
+    /// The input files, for caching the hashes the writer reports.
+    pub input_files: Option<Vec<FileId>>,
 }
 
 impl BeginParams {
-    pub fn new(
-        input_file: PathBuf,
-        compression: CompressionFormat,
-        target: WriteTarget,
-    ) -> std::io::Result<Self> {
-        let input_file_size = ByteSize::b(File::open(&input_file)?.metadata()?.len());
-        Ok(Self {
-            input_file,
-            input_file_size,
-            compression,
-            target,
-        })
-    }
-
     pub fn make_child_config(&self) -> WriterProcessConfig {
         WriterProcessConfig {
             dest: self.target.devnode.clone(),
-            src: self.input_file.clone(),
+            src: self.input.clone(),
             verify: true,
+            verify_strategy: self.verify_strategy,
             compression: self.compression,
+            sync_strategy: self.sync_strategy,
             target_type: self.target.target_type,
+            input_hash: self.input_hash.clone(),
+            digests: self.digests.clone(),
+            implanted_md5: self.implanted_md5.clone(),
+            unmount: self.unmount.clone(),
+            allow_system_disk: self.allow_system_disk,
+            allowed_holders: self.target.details.holders.clone(),
+            expected_identity: match self.target.target_type {
+                Type::File => None,
+                Type::Disk | Type::Partition => Some(self.target.identity()),
+            },
         }
     }
 }
@@ -103,42 +122,198 @@ pub async fn begin_writing(
     log_paths: Arc<LogPaths>,
 ) -> anyhow::Result<()> {
     debug!("Opening TUI");
-    if interactive.is_interactive() {
+    let state = if interactive.is_interactive() {
         debug!("Using fancy interactive TUI");
         let mut tui = TUICapture::new()?;
         let terminal = tui.terminal();
 
         // create app and run it
-        FancyUI::new(&params, handle, terminal, log_paths)
+        let state = FancyUI::new(&params, handle, terminal, log_paths)
             .show()
             .await?;
         debug!("Closing TUI");
+        state
     } else {
         debug!("Using simple TUI");
-        run_simple_burning_ui(handle, params.compression).await?;
+        Some(run_simple_burning_ui(handle, params.compression).await?)
+    };
+
+    if let (Some(state @ WriterState::Finished { error: None, .. }), Some(files)) =
+        (&state, &params.input_files)
+    {
+        let mut cache = HashCache::load();
+        cache_input_hashes(&mut cache, files, &state.hashes().input);
+        if let Err(e) = cache.save() {
+            debug!("Failed to save the hash cache: {e}");
+        }
     }
 
     Ok(())
 }
 
+/// Remembers the input hashes the writer checked, so the next burn of the
+/// same files doesn't have to hash them again.
+fn cache_input_hashes(cache: &mut HashCache, files: &[FileId], hashes: &[InputHash]) {
+    for InputHash { hash, hash_of } in hashes {
+        cache.insert(files, hash.alg, *hash_of, hash.hash.clone());
+    }
+}
+
 impl Display for BeginParams {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Input: {}", self.input_file.to_string_lossy())?;
+        writeln!(f, "Input: {}", self.input.first().to_string_lossy())?;
+        if self.input.is_split() {
+            writeln!(f, "  Parts: {}", self.input.parts().len())?;
+            for p in &self.input.parts()[1..] {
+                writeln!(f, "    {}", p.to_string_lossy())?;
+            }
+        }
         if self.compression.is_identity() {
             writeln!(f, "  Size: {}", self.input_file_size)?;
         } else {
             writeln!(f, "  Size (compressed): {}", self.input_file_size)?;
         }
         writeln!(f, "  Compression: {}", self.compression)?;
+        if let Some(h) = &self.input_hash {
+            for (alg, expected) in &h.expected {
+                writeln!(
+                    f,
+                    "  Hash: {} {} (of {} input, checked while writing)",
+                    alg,
+                    base16::encode_lower(expected),
+                    h.hash_of
+                )?;
+            }
+        }
+        if !self.digests.is_empty() {
+            let algs: Vec<String> = self.digests.iter().map(|a| a.to_string()).collect();
+            writeln!(f, "  Digests: {} (computed while writing)", algs.join(", "))?;
+        }
+        if let Some(sig) = &self.signature {
+            writeln!(f, "  Signature: {}", sig)?;
+        }
+        if let Some(implanted) = &self.implanted_md5 {
+            writeln!(
+                f,
+                "  Implanted MD5: {} (checked while verifying)",
+                base16::encode_lower(&implanted.md5)
+            )?;
+        }
         writeln!(f)?;
 
         writeln!(f, "Output: {}", self.target.name)?;
+        let details = &self.target.details;
+        if let Some(vendor) = &details.vendor {
+            writeln!(f, "  Vendor: {}", vendor)?;
+        }
         writeln!(f, "  Model: {}", self.target.model)?;
+        if let Some(serial) = &details.serial {
+            writeln!(f, "  Serial: {}", serial)?;
+        }
+        if let Some(wwn) = &details.wwn {
+            writeln!(f, "  WWN: {}", wwn)?;
+        }
         writeln!(f, "  Size: {}", self.target.size)?;
         writeln!(f, "  Type: {}", self.target.target_type)?;
+        if self.target.target_type != Type::File {
+            writeln!(f, "  Transport: {}", details.transport)?;
+        }
         writeln!(f, "  Path: {}", self.target.devnode.to_string_lossy())?;
+        if let Some(by_id) = &details.by_id {
+            writeln!(f, "  By ID: {}", by_id.to_string_lossy())?;
+        }
         writeln!(f, "  Removable: {}", self.target.removable)?;
+        if details.system_disk {
+            writeln!(f, "  SYSTEM DISK: the running system is on this disk!")?;
+        }
+        for holder in &details.holders {
+            writeln!(f, "  IN USE: {}", holder)?;
+        }
+        if let Some(ro) = details.read_only {
+            writeln!(f, "  Read-only: {}", if ro { "yes" } else { "no" })?;
+        }
+        if let Some(rotational) = details.rotational {
+            writeln!(f, "  Rotational: {}", if rotational { "yes" } else { "no" })?;
+        }
+        if let (Some(logical), Some(physical)) =
+            (details.logical_block_size, details.physical_block_size)
+        {
+            writeln!(
+                f,
+                "  Block size: {} logical, {} physical",
+                logical, physical
+            )?;
+        }
+        if !details.partitions.is_empty() {
+            match &details.partition_table {
+                Some(table) => writeln!(f, "  Partitions ({}):", table)?,
+                None => writeln!(f, "  Partitions:")?,
+            }
+            for partition in &details.partitions {
+                writeln!(f, "    {}", partition)?;
+            }
+        }
+        for mount in &self.unmount {
+            writeln!(f, "  Unmounting: {}", mount)?;
+        }
+        if self.target.target_type != Type::File {
+            writeln!(f, "  Sync: {}", self.sync_strategy)?;
+        }
+        writeln!(f, "  Verify by: {}", self.verify_strategy)?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        hash::HashAlg,
+        hash_cache::{FileId, HashCache},
+        ui::cli::HashOf,
+        writer_process::ipc::{ComputedHash, InputHash},
+    };
+
+    use super::cache_input_hashes;
+
+    #[test]
+    fn input_hashes_are_cached_by_what_they_are_of() {
+        let image = std::env::temp_dir().join(format!("caligula-start-{}", std::process::id()));
+        std::fs::write(&image, b"hello").unwrap();
+        let files = vec![FileId::of(&image).unwrap()];
+
+        let mut cache = HashCache::default();
+        cache_input_hashes(
+            &mut cache,
+            &files,
+            &[
+                InputHash {
+                    hash: ComputedHash {
+                        alg: HashAlg::Sha256,
+                        hash: vec![1, 2, 3],
+                    },
+                    hash_of: HashOf::Compressed,
+                },
+                InputHash {
+                    hash: ComputedHash {
+                        alg: HashAlg::Md5,
+                        hash: vec![4, 5, 6],
+                    },
+                    hash_of: HashOf::Raw,
+                },
+            ],
+        );
+
+        assert_eq!(
+            cache.get_all(&files, [HashAlg::Sha256], HashOf::Compressed),
+            Some(vec![(HashAlg::Sha256, vec![1, 2, 3])])
+        );
+        assert_eq!(
+            cache.get_all(&files, [HashAlg::Md5], HashOf::Raw),
+            Some(vec![(HashAlg::Md5, vec![4, 5, 6])])
+        );
+        assert_eq!(cache.get_all(&files, [HashAlg::Sha256], HashOf::Raw), None);
+
+        std::fs::remove_file(&image).unwrap();
+    }
+}
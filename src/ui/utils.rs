@@ -5,7 +5,7 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use ratatui::{backend::CrosstermBackend, Terminal};
+use ratatui::{backend::CrosstermBackend, Terminal, TerminalOptions, Viewport};
 use tracing_unwrap::ResultExt;
 
 pub struct TUICapture {
@@ -42,6 +42,45 @@ impl Drop for TUICapture {
     }
 }
 
+/// Like [TUICapture], but only takes over the bottom `height` lines of the
+/// terminal, and clears them when dropped.
+pub struct InlineTUI {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    _private: (),
+}
+
+impl InlineTUI {
+    pub fn new(height: u16) -> anyhow::Result<Self> {
+        enable_raw_mode()?;
+        let backend = CrosstermBackend::new(std::io::stdout());
+        let terminal = Terminal::with_options(
+            backend,
+            TerminalOptions {
+                viewport: Viewport::Inline(height),
+            },
+        )
+        // There's no Self to drop yet, so undo raw mode ourselves.
+        .inspect_err(|_| disable_raw_mode().unwrap_or_log())?;
+
+        Ok(Self {
+            terminal,
+            _private: (),
+        })
+    }
+
+    pub fn terminal(&mut self) -> &mut Terminal<CrosstermBackend<Stdout>> {
+        &mut self.terminal
+    }
+}
+
+impl Drop for InlineTUI {
+    fn drop(&mut self) {
+        self.terminal.clear().unwrap_or_log();
+        disable_raw_mode().unwrap_or_log();
+        self.terminal.show_cursor().unwrap_or_log();
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
 pub struct ByteSpeed(pub f64);
 
@@ -4,7 +4,7 @@ use tracing::{info, trace};
 
 use crate::{
     byteseries::{ByteSeries, EstimatedTime},
-    writer_process::ipc::{ErrorType, StatusMessage},
+    writer_process::ipc::{ComputedHash, ErrorType, InputHash, StatusMessage},
 };
 
 /// A state machine for tracking the state of the writer, based on received
@@ -16,6 +16,7 @@ pub enum WriterState {
         write_hist: ByteSeries,
         verify_hist: ByteSeries,
         total_write_bytes: u64,
+        hashes: WriterHashes,
     },
     Finished {
         finish_time: Instant,
@@ -23,6 +24,7 @@ pub enum WriterState {
         write_hist: ByteSeries,
         verify_hist: Option<ByteSeries>,
         total_write_bytes: u64,
+        hashes: WriterHashes,
     },
 }
 
@@ -40,6 +42,26 @@ impl WriterState {
                 self.on_total_bytes(now, src, dest);
                 self
             }
+            Some(StatusMessage::ImageDigest(hash)) => {
+                info!(?hash, "Received image digest");
+                self.hashes_mut().image.push(hash);
+                self
+            }
+            Some(StatusMessage::InputHash(hash)) => {
+                info!(?hash, "Received input hash");
+                self.hashes_mut().input.push(hash);
+                self
+            }
+            Some(StatusMessage::DeviceHash(hash)) => {
+                info!(?hash, "Received device hash");
+                self.hashes_mut().device.push(hash);
+                self
+            }
+            Some(StatusMessage::ImplantedMd5Verified) => {
+                info!("Received implanted MD5 verification");
+                self.hashes_mut().implanted_md5_verified = true;
+                self
+            }
             Some(StatusMessage::FinishedWriting { verifying }) => {
                 info!("Received finished writing notification");
                 match self {
@@ -82,6 +104,23 @@ impl WriterState {
         }
     }
 
+    /// The hashes the writer has reported so far.
+    pub fn hashes(&self) -> &WriterHashes {
+        match self {
+            Self::Writing(Writing { hashes, .. }) => hashes,
+            Self::Verifying { hashes, .. } => hashes,
+            Self::Finished { hashes, .. } => hashes,
+        }
+    }
+
+    fn hashes_mut(&mut self) -> &mut WriterHashes {
+        match self {
+            Self::Writing(Writing { hashes, .. }) => hashes,
+            Self::Verifying { hashes, .. } => hashes,
+            Self::Finished { hashes, .. } => hashes,
+        }
+    }
+
     fn on_total_bytes(&mut self, now: Instant, src: u64, dest: u64) {
         match self {
             WriterState::Writing(st) => {
@@ -103,11 +142,13 @@ impl WriterState {
                     write_hist: st.write_hist,
                     verify_hist: None,
                     total_write_bytes,
+                    hashes: st.hashes,
                 }
             }
             WriterState::Verifying {
                 write_hist,
                 verify_hist,
+                hashes,
                 ..
             } => {
                 let total_write_bytes = write_hist.bytes_encountered();
@@ -117,6 +158,7 @@ impl WriterState {
                     write_hist,
                     verify_hist: Some(verify_hist),
                     total_write_bytes,
+                    hashes,
                 }
             }
             fin => fin,
@@ -124,19 +166,30 @@ impl WriterState {
     }
 
     pub fn is_finished(&self) -> bool {
-        match self {
-            WriterState::Finished { .. } => true,
-            _ => false,
-        }
+        matches!(self, WriterState::Finished { .. })
     }
 }
 
+/// Hashes reported by the writer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WriterHashes {
+    /// The digests of the image that the user asked for.
+    pub image: Vec<ComputedHash>,
+    /// The hashes of the input, computed while writing.
+    pub input: Vec<InputHash>,
+    /// The hashes of what was written to the device, computed while verifying.
+    pub device: Vec<ComputedHash>,
+    /// Whether the device matched the checksum implanted in the ISO.
+    pub implanted_md5_verified: bool,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Writing {
     pub write_hist: ByteSeries,
     pub total_raw_bytes: Option<u64>,
     pub read_hist: ByteSeries,
     pub input_file_bytes: u64,
+    pub hashes: WriterHashes,
 }
 
 impl Writing {
@@ -150,6 +203,7 @@ impl Writing {
             },
             read_hist: ByteSeries::new(start),
             input_file_bytes,
+            hashes: WriterHashes::default(),
         }
     }
 
@@ -177,6 +231,7 @@ impl Writing {
                 write_hist: self.write_hist,
                 verify_hist: ByteSeries::new(time),
                 total_write_bytes,
+                hashes: self.hashes,
             }
         } else {
             info!(verifying, "Transition to finished");
@@ -186,6 +241,7 @@ impl Writing {
                 write_hist: self.write_hist,
                 verify_hist: None,
                 total_write_bytes,
+                hashes: self.hashes,
             }
         }
     }
@@ -197,10 +253,12 @@ mod tests {
 
     use crate::{
         byteseries::ByteSeries,
-        writer_process::ipc::{ErrorType, StatusMessage},
+        hash::HashAlg,
+        ui::cli::HashOf,
+        writer_process::ipc::{ComputedHash, ErrorType, InputHash, StatusMessage},
     };
 
-    use super::WriterState;
+    use super::{WriterHashes, WriterState};
 
     #[test]
     fn accept_total_bytes_messages() {
@@ -291,6 +349,7 @@ mod tests {
             write_hist: ByteSeries::new(t0),
             verify_hist: None,
             total_write_bytes: 12345678,
+            hashes: WriterHashes::default(),
         };
         let s1 = s0
             .clone()
@@ -309,6 +368,7 @@ mod tests {
             write_hist: ByteSeries::new(t0),
             verify_hist: None,
             total_write_bytes: 12345678,
+            hashes: WriterHashes::default(),
         };
         let s1 = s0.clone().on_status(
             finish_time + Duration::from_secs(2),
@@ -317,4 +377,50 @@ mod tests {
 
         assert_eq!(s1, s0);
     }
+
+    #[test]
+    fn hashes_are_kept_until_finished() {
+        let t0 = Instant::now();
+        let input = InputHash {
+            hash: ComputedHash {
+                alg: HashAlg::Sha256,
+                hash: vec![1, 2, 3],
+            },
+            hash_of: HashOf::Compressed,
+        };
+        let device = ComputedHash {
+            alg: HashAlg::Sha256,
+            hash: vec![4, 5, 6],
+        };
+        let image = ComputedHash {
+            alg: HashAlg::Md5,
+            hash: vec![7, 8, 9],
+        };
+        let s = WriterState::initial(t0, false, 80)
+            .on_status(t0, Some(StatusMessage::ImageDigest(image.clone())))
+            .on_status(
+                t0 + Duration::from_secs(1),
+                Some(StatusMessage::InputHash(input.clone())),
+            )
+            .on_status(
+                t0 + Duration::from_secs(2),
+                Some(StatusMessage::FinishedWriting { verifying: true }),
+            )
+            .on_status(
+                t0 + Duration::from_secs(3),
+                Some(StatusMessage::DeviceHash(device.clone())),
+            )
+            .on_status(t0 + Duration::from_secs(4), Some(StatusMessage::Success));
+
+        assert!(s.is_finished());
+        assert_eq!(
+            s.hashes(),
+            &WriterHashes {
+                image: vec![image],
+                input: vec![input],
+                device: vec![device],
+                implanted_md5_verified: false,
+            }
+        );
+    }
 }
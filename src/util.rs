@@ -21,3 +21,32 @@ pub async fn ensure_state_dir() -> Result<PathBuf, futures_io::Error> {
 
     Ok(dir)
 }
+
+/// The per-user directory for data that is worth keeping between invocations,
+/// but that can be thrown away at any time.
+pub fn user_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty()) {
+        return Some(dir.into());
+    }
+
+    let home = PathBuf::from(env::var_os("HOME").filter(|d| !d.is_empty())?);
+    if cfg!(target_os = "macos") {
+        Some(home.join("Library").join("Caches"))
+    } else {
+        Some(home.join(".cache"))
+    }
+}
+
+/// The per-user directory for configuration files.
+pub fn user_config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(dir.into());
+    }
+
+    let home = PathBuf::from(env::var_os("HOME").filter(|d| !d.is_empty())?);
+    if cfg!(target_os = "macos") {
+        Some(home.join("Library").join("Application Support"))
+    } else {
+        Some(home.join(".config"))
+    }
+}
@@ -1,23 +1,45 @@
 use std::io::BufReader;
 use std::{
     fs::File,
-    io::{self, Read, Seek, Write},
+    io::{self, Read, Seek, SeekFrom, Write},
 };
 
 use bytesize::ByteSize;
+use digest::Update;
 use interprocess::local_socket::{prelude::*, GenericFilePath};
 use tracing::{debug, info, trace};
 use tracing_unwrap::ResultExt;
 
+use crate::allowlist;
 use crate::childproc_common::child_init;
-use crate::compression::decompress;
-use crate::device;
+use crate::compression::{decompress, CompressionFormat};
+use crate::device::{self, DeviceIdentity, WriteTarget};
+use crate::hash::{HashAlg, HashRead, Hasher, MultiHasher};
 use crate::ipc_common::write_msg;
+use crate::isomd5::{ImplantedMd5, ImplantedMd5Hasher};
+use crate::mounts::{holders_of, MountTable};
+use crate::multipart::MultiPartRead;
+use crate::system_disks::SystemDisks;
+use crate::ui::cli::HashOf;
 
-use crate::writer_process::xplat::open_blockdev;
+use crate::writer_process::device_writer::DeviceWriter;
+use crate::writer_process::xplat::is_read_only;
 
 use super::ipc::*;
 
+const BLOCK_SIZE: ByteSize = ByteSize::kb(512);
+
+/// How many blocks to process between progress reports.
+const CHECKPOINT_BLOCKS: usize = 32;
+
+/// The algorithm used for [VerifyStrategy::Hash]. This never leaves the
+/// writer, so it only needs to be fast.
+const VERIFY_HASH_ALG: HashAlg = HashAlg::Blake3;
+
+/// The algorithm used to report the hash of the device, if the user did not
+/// give us any hashes.
+const DEFAULT_DEVICE_HASH_ALG: HashAlg = HashAlg::Sha256;
+
 /// This is intended to be run in a forked child process, possibly with
 /// escalated permissions.
 #[tokio::main]
@@ -39,14 +61,13 @@ pub async fn main() {
 }
 
 fn run(mut tx: impl Write, args: &WriterProcessConfig) -> Result<(), ErrorType> {
-    debug!("Opening file {}", args.src.to_string_lossy());
-    let mut src = File::open(&args.src).unwrap_or_log();
-    let size = src.seek(io::SeekFrom::End(0))?;
-    src.seek(io::SeekFrom::Start(0))?;
+    debug!("Opening file {}", args.src);
+    let mut src = args.src.open().unwrap_or_log();
+    let size = src.len();
 
     debug!(size, "Got input file size");
 
-    write(&mut tx, args, &mut src, size)?;
+    let (written, input_hashes) = write(&mut tx, args, &mut src, size)?;
     send_msg(
         &mut tx,
         StatusMessage::FinishedWriting {
@@ -58,65 +79,370 @@ fn run(mut tx: impl Write, args: &WriterProcessConfig) -> Result<(), ErrorType>
         return Ok(());
     }
 
-    src.seek(io::SeekFrom::Start(0))?;
-    verify(tx, args, &mut src)?;
+    // The device is hashed as it's read back, so that we have a record of
+    // exactly what ended up on it.
+    let mut device_hasher = DeviceHasher {
+        hashes: match &args.input_hash {
+            Some(h) => MultiHasher::new(h.algs()),
+            None => MultiHasher::new([DEFAULT_DEVICE_HASH_ALG]),
+        },
+        implanted_md5: args.implanted_md5.as_ref().map(ImplantedMd5Hasher::new),
+    };
+    match written {
+        Some(written) => verify_hash(&mut tx, args, &written, &mut device_hasher)?,
+        None => {
+            src.seek(SeekFrom::Start(0))?;
+            verify(&mut tx, args, &mut src, &mut device_hasher)?;
+        }
+    }
+
+    check_device_hash(&mut tx, &input_hashes, device_hasher.hashes)?;
+    if let (Some(implanted), Some(hasher)) = (&args.implanted_md5, device_hasher.implanted_md5) {
+        check_implanted_md5(tx, implanted, hasher)?;
+    }
+    Ok(())
+}
+
+fn check_identity(expected: &DeviceIdentity, file: &File) -> Result<(), ErrorType> {
+    let actual = match WriteTarget::of_open_file(file) {
+        Ok(Some(target)) => target.identity(),
+        Ok(None) => {
+            debug!("Can't look up the opened device, skipping identity check");
+            return Ok(());
+        }
+        Err(e) => return Err(ErrorType::UnknownChildProcError(e.to_string())),
+    };
+    info!(
+        ?expected,
+        ?actual,
+        "Checking the identity of the opened device"
+    );
+
+    let differences = expected.differences(&actual);
+    if !differences.is_empty() {
+        return Err(ErrorType::TargetChanged { differences });
+    }
+    Ok(())
+}
+
+/// A summary of the data written, for [VerifyStrategy::Hash].
+struct WrittenData {
+    len: u64,
+    hash: Vec<u8>,
+    /// How much of the input file was read to write it.
+    src_len: u64,
+}
+
+impl WrittenData {
+    /// Roughly where in the input file we'd be, having got `offset` bytes
+    /// into the data, for reporting progress.
+    fn src_position(&self, offset: u64) -> u64 {
+        if self.len == 0 {
+            return self.src_len;
+        }
+        (offset as u128 * self.src_len as u128 / self.len as u128) as u64
+    }
+}
+
+/// Checks that we may write to the target, before opening it.
+fn check_target(args: &WriterProcessConfig) -> Result<(), ErrorType> {
+    // This was checked before starting us too, but this is our last chance
+    // to catch a target that changed in the meantime, or a buggy caller.
+    allowlist::check(&args.dest, &[])?;
+    if !args.allow_system_disk && SystemDisks::load()?.contains(&args.dest)? {
+        return Err(ErrorType::SystemDisk);
+    }
 
+    let holders: Vec<String> = holders_of(&args.dest)?
+        .into_iter()
+        .filter(|h| !args.allowed_holders.contains(h))
+        .map(|h| h.to_string())
+        .collect();
+    if !holders.is_empty() {
+        return Err(ErrorType::DeviceBusy { holders });
+    }
     Ok(())
 }
 
 fn write(
     mut tx: impl Write,
     args: &WriterProcessConfig,
-    src: &mut File,
+    src: &mut MultiPartRead<File>,
     input_file_bytes: u64,
-) -> Result<(), ErrorType> {
+) -> Result<(Option<WrittenData>, Vec<InputHash>), ErrorType> {
+    check_target(args)?;
+
     debug!("Opening {} for writing", args.dest.to_string_lossy());
 
-    let file = match args.target_type {
-        device::Type::File => File::create(&args.dest)?,
+    let mut file = match args.target_type {
+        device::Type::File => DeviceWriter::file(File::create(&args.dest)?),
         device::Type::Disk | device::Type::Partition => {
-            open_blockdev(&args.dest, args.compression)?
+            DeviceWriter::open(&args.dest, args.sync_strategy)?
         }
     };
+
+    // Writes to a read-only disk fail with a permission error, which would
+    // otherwise send the user off to escalate for nothing.
+    if args.target_type != device::Type::File && is_read_only(file.get_ref())? {
+        return Err(ErrorType::ReadOnlyDevice);
+    }
+
+    // The name may have been given to another device since the user
+    // confirmed it, so check what we actually opened.
+    if let Some(expected) = &args.expected_identity {
+        check_identity(expected, file.get_ref())?;
+    }
+
+    // Something, like an automounter, may have mounted it since the user
+    // confirmed. Only the mounts they agreed to unmount may be in the way.
+    if args.target_type != device::Type::File {
+        let mounts: Vec<String> = MountTable::load()?
+            .mounts_of_devnode(&args.dest)?
+            .into_iter()
+            .filter(|m| !args.unmount.contains(m))
+            .map(|m| m.to_string())
+            .collect();
+        if !mounts.is_empty() {
+            return Err(ErrorType::DeviceBusy { holders: mounts });
+        }
+    }
+
+    for mount in &args.unmount {
+        info!(%mount, "Unmounting");
+        mount.unmount().map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => ErrorType::PermissionDenied,
+            _ => ErrorType::UnmountFailed {
+                mount: mount.to_string(),
+                error: e.to_string(),
+            },
+        })?;
+    }
     send_msg(
         &mut tx,
         StatusMessage::InitSuccess(InitialInfo { input_file_bytes }),
     );
 
-    for_each_block(&mut tx, args, src, WriteSink { file })
+    let mut hasher = InputHasher::new(args.input_hash.as_ref(), &args.digests, args.compression);
+    let written = if args.verify && args.verify_strategy == VerifyStrategy::Hash {
+        let mut sink = HashSink::new(WriteSink { file: &mut file });
+        let src_len = for_each_block(&mut tx, args, src, &mut sink, hasher.as_mut())?;
+        Some(sink.finish(src_len))
+    } else {
+        let mut sink = WriteSink { file: &mut file };
+        for_each_block(&mut tx, args, src, &mut sink, hasher.as_mut())?;
+        None
+    };
+    file.finish().map_err(write_error)?;
+
+    let Some(hasher) = hasher else {
+        return Ok((written, vec![]));
+    };
+    for (alg, hash) in hasher.digests.finalize() {
+        debug!(%alg, hash = base16::encode_lower(&hash), "Finished image digest");
+        send_msg(
+            &mut tx,
+            StatusMessage::ImageDigest(ComputedHash { alg, hash }),
+        );
+    }
+
+    let input_hashes = match &args.input_hash {
+        Some(expected) => {
+            let raw = hasher.raw.finalize();
+            let compressed = hasher.compressed.finalize();
+            debug!(?raw, ?compressed, "Finished hashing input");
+
+            let input_hashes = expected.check(&raw, &compressed)?;
+            for h in &input_hashes {
+                send_msg(&mut tx, StatusMessage::InputHash(h.clone()));
+            }
+            input_hashes
+        }
+        None => vec![],
+    };
+
+    Ok((written, input_hashes))
 }
 
-fn verify(tx: impl Write, args: &WriterProcessConfig, src: &mut File) -> Result<(), ErrorType> {
+fn verify(
+    tx: impl Write,
+    args: &WriterProcessConfig,
+    src: &mut MultiPartRead<File>,
+    device_hasher: &mut DeviceHasher,
+) -> Result<(), ErrorType> {
     debug!("Opening {} for verification", args.dest.to_string_lossy());
 
-    let file = File::open(&args.dest)?;
-    for_each_block(tx, args, src, VerifySink { file })
+    let file = HashRead::new(File::open(&args.dest)?, Some(device_hasher));
+    for_each_block(tx, args, src, &mut VerifySink { file }, None)?;
+    Ok(())
+}
+
+/// Reads back what was written, and checks that it hashes the same as the
+/// data that we wrote.
+fn verify_hash(
+    mut tx: impl Write,
+    args: &WriterProcessConfig,
+    written: &WrittenData,
+    device_hasher: &mut DeviceHasher,
+) -> Result<(), ErrorType> {
+    debug!(
+        "Opening {} for verification by hash",
+        args.dest.to_string_lossy()
+    );
+
+    let mut file = HashRead::new(
+        File::open(&args.dest)?.take(written.len),
+        Some(device_hasher),
+    );
+    let mut hasher = Hasher::new(VERIFY_HASH_ALG);
+    let mut block = vec![0u8; BLOCK_SIZE.as_u64() as usize];
+    let mut offset: u64 = 0;
+
+    'outer: loop {
+        for _ in 0..CHECKPOINT_BLOCKS {
+            let read_bytes = file.read(&mut block)?;
+            if read_bytes == 0 {
+                break 'outer;
+            }
+
+            trace!(block_len = read_bytes, "Hashing block");
+            hasher.update(&block[..read_bytes]);
+            offset += read_bytes as u64;
+        }
+
+        send_msg(
+            &mut tx,
+            StatusMessage::TotalBytes {
+                src: written.src_position(offset),
+                dest: offset,
+            },
+        );
+    }
+
+    send_msg(
+        tx,
+        StatusMessage::TotalBytes {
+            src: written.src_position(offset),
+            dest: offset,
+        },
+    );
+
+    if offset != written.len {
+        return Err(ErrorType::EndOfOutput);
+    }
+    if hasher.finalize() != written.hash {
+        return Err(ErrorType::VerificationFailed);
+    }
+    Ok(())
+}
+
+/// Reports the hashes of the device, and checks them against the input hashes
+/// that are of the data we wrote.
+fn check_device_hash(
+    mut tx: impl Write,
+    input_hashes: &[InputHash],
+    device_hasher: MultiHasher,
+) -> Result<(), ErrorType> {
+    let hashes = device_hasher.finalize();
+    for (alg, hash) in &hashes {
+        debug!(%alg, hash = base16::encode_lower(hash), "Finished hashing device");
+        send_msg(
+            &mut tx,
+            StatusMessage::DeviceHash(ComputedHash {
+                alg: *alg,
+                hash: hash.clone(),
+            }),
+        );
+    }
+
+    for expected in input_hashes.iter().filter(|h| h.hash_of == HashOf::Raw) {
+        let ComputedHash {
+            alg,
+            hash: expected,
+        } = &expected.hash;
+        let actual = hashes
+            .iter()
+            .find(|(a, _)| a == alg)
+            .map(|(_, h)| h)
+            .expect("The device should be hashed with every input algorithm");
+        if actual != expected {
+            return Err(ErrorType::DeviceHashMismatch {
+                alg: *alg,
+                expected: expected.clone(),
+                actual: actual.clone(),
+            });
+        }
+    }
+    Ok(())
 }
 
+/// Checks the checksum implanted in the ISO against what was read back from
+/// the device.
+fn check_implanted_md5(
+    tx: impl Write,
+    implanted: &ImplantedMd5,
+    hasher: ImplantedMd5Hasher,
+) -> Result<(), ErrorType> {
+    let Some(actual) = hasher.finalize() else {
+        return Err(ErrorType::EndOfOutput);
+    };
+    debug!(
+        md5 = base16::encode_lower(&actual),
+        "Finished checking implanted MD5"
+    );
+
+    if actual != implanted.md5 {
+        return Err(ErrorType::ImplantedMd5Mismatch {
+            expected: implanted.md5.clone(),
+            actual,
+        });
+    }
+    send_msg(tx, StatusMessage::ImplantedMd5Verified);
+    Ok(())
+}
+
+/// Feeds the input to `sink` block by block. Returns how much of the input
+/// file was read.
 #[inline]
 fn for_each_block(
     mut tx: impl Write,
     args: &WriterProcessConfig,
     src: impl Read + Seek,
-    mut sink: impl BlockSink,
-) -> Result<(), ErrorType> {
-    let block_size = ByteSize::kb(512).as_u64() as usize;
+    sink: &mut impl BlockSink,
+    hasher: Option<&mut InputHasher>,
+) -> Result<u64, ErrorType> {
+    let block_size = BLOCK_SIZE.as_u64() as usize;
     let mut read_block = vec![0u8; block_size];
     let mut scratch_block = vec![0u8; block_size]; // A block for the user to mutate
 
+    let (mut raw_hasher, mut digests, file_hasher) = match hasher {
+        Some(InputHasher {
+            raw,
+            compressed,
+            digests,
+        }) => (Some(raw), Some(digests), Some(compressed)),
+        None => (None, None, None),
+    };
+    let hashing_file = file_hasher.as_ref().is_some_and(|h| !h.is_empty());
+    let src = HashRead::new(src, file_hasher);
+
     let mut decompress = decompress(args.compression, BufReader::new(src))
         .expect("Failed to open input file with decompressor");
 
-    let checkpoint_blocks: usize = 32;
     let mut offset: u64 = 0;
 
     'outer: loop {
-        for _ in 0..checkpoint_blocks {
+        for _ in 0..CHECKPOINT_BLOCKS {
             let read_bytes = decompress.read(&mut read_block)?;
             if read_bytes == 0 {
                 break 'outer;
             }
 
+            if let Some(h) = &mut raw_hasher {
+                h.update(&read_block[..read_bytes]);
+            }
+            if let Some(h) = &mut digests {
+                h.update(&read_block[..read_bytes]);
+            }
             sink.on_block(&read_block[..read_bytes], &mut scratch_block[..read_bytes])?;
             offset += read_bytes as u64;
         }
@@ -131,16 +457,23 @@ fn for_each_block(
         );
     }
 
+    if hashing_file {
+        // Decompressors may stop before the end of the file (i.e. if there is
+        // trailing padding), but the hash covers all of it.
+        io::copy(decompress.get_mut(), &mut io::sink())?;
+    }
+
     sink.on_checkpoint()?;
+    let src_len = decompress.get_mut().stream_position()?;
     send_msg(
         tx,
         StatusMessage::TotalBytes {
-            src: decompress.get_mut().stream_position()?,
+            src: src_len,
             dest: offset,
         },
     );
 
-    Ok(())
+    Ok(src_len)
 }
 
 #[inline]
@@ -149,6 +482,60 @@ pub fn send_msg(mut tx: impl Write, msg: StatusMessage) {
     tx.flush().expect("Failed to flush stream");
 }
 
+/// Hashes the input while it's being written, so that it doesn't have to be
+/// read in a separate pass beforehand.
+struct InputHasher {
+    /// Hashes the decompressed data.
+    raw: MultiHasher,
+    /// Hashes the input file as-is.
+    compressed: MultiHasher,
+    /// Hashes the decompressed data, for the digests the user asked for.
+    digests: MultiHasher,
+}
+
+impl InputHasher {
+    /// Returns None if there is nothing to hash.
+    fn new(
+        expected: Option<&ExpectedHash>,
+        digests: &[HashAlg],
+        cf: CompressionFormat,
+    ) -> Option<Self> {
+        if expected.is_none() && digests.is_empty() {
+            return None;
+        }
+        let (raw, compressed) = expected.map_or((false, false), |e| e.hashed_streams(cf));
+        let hasher = |enabled: bool| {
+            MultiHasher::new(
+                expected
+                    .into_iter()
+                    .flat_map(|e| e.algs())
+                    .filter(|_| enabled),
+            )
+        };
+
+        Some(Self {
+            raw: hasher(raw),
+            compressed: hasher(compressed),
+            digests: MultiHasher::new(digests.iter().copied()),
+        })
+    }
+}
+
+/// Everything that the device is hashed with while it's read back.
+struct DeviceHasher {
+    hashes: MultiHasher,
+    implanted_md5: Option<ImplantedMd5Hasher>,
+}
+
+impl Update for DeviceHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.hashes.update(data);
+        if let Some(h) = &mut self.implanted_md5 {
+            h.update(data);
+        }
+    }
+}
+
 trait BlockSink {
     fn on_block(&mut self, block: &[u8], scratch: &mut [u8]) -> Result<(), ErrorType>;
     fn on_checkpoint(&mut self) -> Result<(), ErrorType>;
@@ -169,20 +556,65 @@ where
     fn on_block(&mut self, block: &[u8], _scratch: &mut [u8]) -> Result<(), ErrorType> {
         trace!(block_len = block.len(), "Writing block");
 
-        let written = self
-            .file
-            .write(block)
-            .expect("Failed to write block to disk");
-        if written != block.len() {
-            return Err(ErrorType::EndOfOutput);
+        self.file.write_all(block).map_err(write_error)
+    }
+
+    #[inline]
+    fn on_checkpoint(&mut self) -> Result<(), ErrorType> {
+        // Buffered data may only reach the disk here, so this can run out
+        // of room too.
+        self.file.flush().map_err(write_error)
+    }
+}
+
+/// Tells running out of room on the target apart from other errors.
+fn write_error(e: io::Error) -> ErrorType {
+    match e.kind() {
+        io::ErrorKind::WriteZero | io::ErrorKind::StorageFull => ErrorType::EndOfOutput,
+        _ => e.into(),
+    }
+}
+
+/// Hashes everything that passes through to the inner sink.
+struct HashSink<S> {
+    inner: S,
+    hasher: Hasher,
+    len: u64,
+}
+
+impl<S> HashSink<S> {
+    fn new(inner: S) -> Self {
+        Self {
+            inner,
+            hasher: Hasher::new(VERIFY_HASH_ALG),
+            len: 0,
         }
+    }
+
+    fn finish(self, src_len: u64) -> WrittenData {
+        WrittenData {
+            len: self.len,
+            hash: self.hasher.finalize(),
+            src_len,
+        }
+    }
+}
+
+impl<S> BlockSink for HashSink<S>
+where
+    S: BlockSink,
+{
+    #[inline]
+    fn on_block(&mut self, block: &[u8], scratch: &mut [u8]) -> Result<(), ErrorType> {
+        self.inner.on_block(block, scratch)?;
+        self.hasher.update(block);
+        self.len += block.len() as u64;
         Ok(())
     }
 
     #[inline]
     fn on_checkpoint(&mut self) -> Result<(), ErrorType> {
-        self.file.flush()?;
-        Ok(())
+        self.inner.on_checkpoint()
     }
 }
 
@@ -222,13 +654,32 @@ where
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use std::io::{Cursor, Write};
 
+    use flate2::{write::GzEncoder, Compression};
+    use md5::Md5;
     use rand::{thread_rng, RngCore};
+    use sha2::{Digest, Sha256};
+    use test_case::test_case;
+
+    use crate::{
+        compression::CompressionFormat,
+        device,
+        hash::{HashAlg, MultiHasher},
+        ipc_common::write_msg,
+        multipart::ImageParts,
+        ui::cli::{HashOf, SyncStrategy},
+        writer_process::{
+            child::VerifySink,
+            device_writer::{tests::StrictWriter, AlignedWriter},
+            ipc::{ErrorType, ExpectedHash, StatusMessage, VerifyStrategy, WriterProcessConfig},
+        },
+    };
 
-    use crate::writer_process::{child::VerifySink, ipc::ErrorType};
-
-    use super::{BlockSink, WriteSink};
+    use super::{
+        check_target, for_each_block, verify_hash, BlockSink, DeviceHasher, HashSink, InputHasher,
+        WriteSink,
+    };
 
     fn make_random(n: usize) -> Vec<u8> {
         let mut rng = thread_rng();
@@ -248,6 +699,25 @@ mod tests {
         assert_eq!(sink.file, vec![1, 2, 3, 4, 1, 2, 3, 4, 5, 6]);
     }
 
+    #[test]
+    fn running_out_of_room_on_checkpoint_is_end_of_output() {
+        let disk = StrictWriter {
+            alignment: 512,
+            written: vec![],
+            room: 4096,
+        };
+        let mut sink = WriteSink {
+            file: AlignedWriter::new(disk, 512, 1 << 20),
+        };
+
+        // This all fits in the buffer, so the disk only fills up once it's
+        // flushed.
+        sink.on_block(&make_random(10_000), &mut make_random(10_000))
+            .unwrap();
+
+        assert_eq!(sink.on_checkpoint(), Err(ErrorType::EndOfOutput));
+    }
+
     #[test]
     fn verify_sink_multiple_blocks_incorrect() {
         let src = make_random(1000);
@@ -280,4 +750,187 @@ mod tests {
         sink.on_block(&src[..500], &mut make_random(500)).unwrap();
         sink.on_block(&src[500..], &mut make_random(500)).unwrap();
     }
+
+    #[test_case(HashOf::Raw, HashOf::Raw => Some(HashOf::Raw); "raw")]
+    #[test_case(HashOf::Compressed, HashOf::Compressed => Some(HashOf::Compressed); "compressed")]
+    #[test_case(HashOf::Auto, HashOf::Raw => Some(HashOf::Raw); "auto matching raw")]
+    #[test_case(HashOf::Auto, HashOf::Compressed => Some(HashOf::Compressed); "auto matching compressed")]
+    #[test_case(HashOf::Raw, HashOf::Compressed => None; "mismatch")]
+    fn hashes_input_while_writing(hash_of: HashOf, actually_of: HashOf) -> Option<HashOf> {
+        let raw = make_random(2_000_000);
+        let mut encoder = GzEncoder::new(vec![], Compression::fast());
+        encoder.write_all(&raw).unwrap();
+        let mut compressed = encoder.finish().unwrap();
+        // Padding after the end of the stream should still be hashed.
+        compressed.extend_from_slice(&[0; 100]);
+
+        let args = WriterProcessConfig {
+            dest: "/dev/null".into(),
+            src: ImageParts::explicit(vec!["image.gz".into()]),
+            verify: false,
+            verify_strategy: VerifyStrategy::Compare,
+            compression: CompressionFormat::Gz,
+            sync_strategy: SyncStrategy::Direct,
+            target_type: device::Type::File,
+            input_hash: None,
+            digests: vec![],
+            implanted_md5: None,
+            unmount: vec![],
+            allow_system_disk: false,
+            allowed_holders: vec![],
+            expected_identity: None,
+        };
+        let hashed = match actually_of {
+            HashOf::Compressed => &compressed,
+            _ => &raw,
+        };
+        let expected = ExpectedHash {
+            expected: vec![
+                (HashAlg::Sha256, Sha256::digest(hashed).to_vec()),
+                (HashAlg::Md5, Md5::digest(hashed).to_vec()),
+            ],
+            hash_of,
+        };
+        let mut hasher = InputHasher::new(Some(&expected), &[], CompressionFormat::Gz).unwrap();
+        let mut written = vec![];
+
+        for_each_block(
+            vec![],
+            &args,
+            Cursor::new(&compressed),
+            &mut WriteSink { file: &mut written },
+            Some(&mut hasher),
+        )
+        .unwrap();
+        assert!(written == raw);
+
+        let found = expected
+            .check(&hasher.raw.finalize(), &hasher.compressed.finalize())
+            .ok()?;
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].hash_of, found[1].hash_of);
+        Some(found[0].hash_of)
+    }
+
+    #[test]
+    fn computes_image_digests() {
+        let raw = make_random(3_000_000);
+        let mut compressed = GzEncoder::new(vec![], Compression::fast());
+        compressed.write_all(&raw).unwrap();
+        let compressed = compressed.finish().unwrap();
+        let mut args = file_config(std::path::Path::new("out"));
+        args.compression = CompressionFormat::Gz;
+        let mut hasher =
+            InputHasher::new(None, &[HashAlg::Md5, HashAlg::Sha256], args.compression).unwrap();
+
+        for_each_block(
+            vec![],
+            &args,
+            Cursor::new(&compressed),
+            &mut WriteSink { file: vec![] },
+            Some(&mut hasher),
+        )
+        .unwrap();
+
+        assert_eq!(
+            hasher.digests.finalize(),
+            vec![
+                (HashAlg::Md5, Md5::digest(&raw).to_vec()),
+                (HashAlg::Sha256, Sha256::digest(&raw).to_vec()),
+            ]
+        );
+    }
+
+    fn file_config(dest: &std::path::Path) -> WriterProcessConfig {
+        WriterProcessConfig {
+            dest: dest.to_owned(),
+            src: ImageParts::explicit(vec!["image".into()]),
+            verify: true,
+            verify_strategy: VerifyStrategy::Hash,
+            compression: CompressionFormat::Identity,
+            sync_strategy: SyncStrategy::Direct,
+            target_type: device::Type::File,
+            input_hash: None,
+            digests: vec![],
+            implanted_md5: None,
+            unmount: vec![],
+            allow_system_disk: false,
+            allowed_holders: vec![],
+            expected_identity: None,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn refuses_system_disk_unless_allowed() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dev = std::fs::metadata("/").unwrap().dev();
+        let sysnode = format!("/sys/dev/block/{}:{}", libc::major(dev), libc::minor(dev));
+        let Ok(sysnode) = std::fs::canonicalize(sysnode) else {
+            // The root filesystem isn't on a block device here.
+            return;
+        };
+        let devnode = std::path::Path::new("/dev").join(sysnode.file_name().unwrap());
+        if !devnode.exists() {
+            return;
+        }
+        let mut args = file_config(&devnode);
+        args.target_type = device::Type::Disk;
+
+        assert_eq!(check_target(&args), Err(ErrorType::SystemDisk));
+        args.allow_system_disk = true;
+        assert_ne!(check_target(&args), Err(ErrorType::SystemDisk));
+    }
+
+    #[test_case(None => Ok(()); "intact")]
+    #[test_case(Some(1_234_567) => Err(ErrorType::VerificationFailed); "corrupted")]
+    fn verify_by_hash(corrupt_at: Option<usize>) -> Result<(), ErrorType> {
+        let src = make_random(2_000_000);
+        let mut sink = HashSink::new(WriteSink { file: vec![] });
+        for chunk in src.chunks(300_000) {
+            sink.on_block(chunk, &mut make_random(chunk.len())).unwrap();
+        }
+        let mut disk = sink.inner.file.clone();
+        // As if the input was compressed to a quarter of its size.
+        let written = sink.finish(500_000);
+        assert_eq!(written.len, src.len() as u64);
+
+        if let Some(i) = corrupt_at {
+            disk[i] ^= 1;
+        }
+        // The disk is usually bigger than what was written to it.
+        disk.extend_from_slice(&[0; 1000]);
+
+        let dest =
+            std::env::temp_dir().join(format!("caligula-verify-hash-{}", thread_rng().next_u64()));
+        std::fs::write(&dest, &disk).unwrap();
+        let mut device_hasher = DeviceHasher {
+            hashes: MultiHasher::new([HashAlg::Sha256]),
+            implanted_md5: None,
+        };
+        let mut msgs = vec![];
+        let result = verify_hash(&mut msgs, &file_config(&dest), &written, &mut device_hasher);
+        std::fs::remove_file(&dest).unwrap();
+
+        if result.is_ok() {
+            assert_eq!(
+                device_hasher.hashes.finalize(),
+                vec![(HashAlg::Sha256, Sha256::digest(&src).to_vec())]
+            );
+            // Progress through the input is reported in terms of the input
+            // file, not the data in it.
+            let mut last = vec![];
+            write_msg(
+                &mut last,
+                &StatusMessage::TotalBytes {
+                    src: 500_000,
+                    dest: 2_000_000,
+                },
+            )
+            .unwrap();
+            assert!(msgs.ends_with(&last));
+        }
+        result
+    }
 }
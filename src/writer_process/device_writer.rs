@@ -0,0 +1,252 @@
+//! Writing to the target in a way that makes sure that the data actually
+//! reaches it, rather than piling up in the page cache.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use tracing::{debug, warn};
+
+use crate::ui::cli::SyncStrategy;
+
+use super::xplat::{direct_io_alignment, disable_direct_io, open_blockdev};
+
+/// How much to buffer before writing, with [SyncStrategy::Direct].
+const DIRECT_BUFFER_SIZE: usize = 1 << 20;
+
+pub enum DeviceWriter {
+    Direct(AlignedWriter<File>),
+    Plain {
+        file: File,
+        /// Whether to wait for the disk to catch up on every flush.
+        sync_on_flush: bool,
+    },
+}
+
+impl DeviceWriter {
+    /// Opens a block device for writing with the given strategy. If the
+    /// device can't do direct I/O, it falls back to [SyncStrategy::Sync].
+    pub fn open(path: &Path, strategy: SyncStrategy) -> io::Result<Self> {
+        if strategy == SyncStrategy::Direct {
+            match open_blockdev(path, strategy) {
+                Ok(file) => {
+                    return Ok(match direct_io_alignment(&file)? {
+                        Some(alignment) => {
+                            debug!(alignment, "Opened for direct I/O");
+                            Self::Direct(AlignedWriter::new(file, alignment, DIRECT_BUFFER_SIZE))
+                        }
+                        None => Self::Plain {
+                            file,
+                            sync_on_flush: false,
+                        },
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                    warn!("Direct I/O is not supported, falling back to O_SYNC: {e}");
+                    return Self::open(path, SyncStrategy::Sync);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self::Plain {
+            file: open_blockdev(path, strategy)?,
+            sync_on_flush: strategy == SyncStrategy::Buffered,
+        })
+    }
+
+    /// Writes to a regular file.
+    pub fn file(file: File) -> Self {
+        Self::Plain {
+            file,
+            sync_on_flush: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &File {
+        match self {
+            Self::Direct(w) => &w.inner,
+            Self::Plain { file, .. } => file,
+        }
+    }
+
+    /// Writes out whatever is left, and waits for it all to reach the disk.
+    /// With direct I/O, the last block may only be partly filled, so it goes
+    /// through the page cache instead.
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            Self::Direct(w) => {
+                let (mut file, tail) = w.into_parts()?;
+                if !tail.is_empty() {
+                    debug!(len = tail.len(), "Writing partial last block");
+                    disable_direct_io(&file)?;
+                    file.write_all(&tail)?;
+                }
+                file
+            }
+            Self::Plain { file, .. } => file,
+        };
+        file.flush()?;
+        file.sync_data()
+    }
+}
+
+impl Write for DeviceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Direct(w) => w.write(buf),
+            Self::Plain { file, .. } => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Direct(w) => w.flush(),
+            Self::Plain {
+                file,
+                sync_on_flush: true,
+            } => file.sync_data(),
+            Self::Plain { file, .. } => file.flush(),
+        }
+    }
+}
+
+/// Buffers writes so that the inner writer only ever gets whole blocks, from
+/// memory aligned to the block size, like O_DIRECT needs.
+pub struct AlignedWriter<W> {
+    inner: W,
+    alignment: usize,
+    storage: Vec<u8>,
+    /// Where the aligned part of `storage` starts.
+    start: usize,
+    capacity: usize,
+    filled: usize,
+}
+
+impl<W: Write> AlignedWriter<W> {
+    /// `alignment` must be a power of two.
+    pub fn new(inner: W, alignment: usize, capacity: usize) -> Self {
+        let capacity = capacity.div_ceil(alignment).max(1) * alignment;
+        let storage = vec![0u8; capacity + alignment];
+        let start = storage.as_ptr().align_offset(alignment);
+        Self {
+            inner,
+            alignment,
+            storage,
+            start,
+            capacity,
+            filled: 0,
+        }
+    }
+
+    /// Writes out all the whole blocks in the buffer, and keeps the rest.
+    fn write_blocks(&mut self) -> io::Result<()> {
+        let len = self.filled / self.alignment * self.alignment;
+        if len == 0 {
+            return Ok(());
+        }
+        let buf = &mut self.storage[self.start..self.start + self.capacity];
+        self.inner.write_all(&buf[..len])?;
+        buf.copy_within(len..self.filled, 0);
+        self.filled -= len;
+        Ok(())
+    }
+
+    /// Writes out all the whole blocks, and returns the inner writer along
+    /// with the rest, which is less than a block.
+    pub fn into_parts(mut self) -> io::Result<(W, Vec<u8>)> {
+        self.write_blocks()?;
+        let tail = self.storage[self.start..self.start + self.filled].to_vec();
+        Ok((self.inner, tail))
+    }
+}
+
+impl<W: Write> Write for AlignedWriter<W> {
+    fn write(&mut self, mut data: &[u8]) -> io::Result<usize> {
+        let len = data.len();
+        while !data.is_empty() {
+            let n = data.len().min(self.capacity - self.filled);
+            let at = self.start + self.filled;
+            self.storage[at..at + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == self.capacity {
+                self.write_blocks()?;
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_blocks()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::io::{self, Write};
+
+    use test_case::test_case;
+
+    use super::AlignedWriter;
+
+    /// Records what it's given, and fails like O_DIRECT would on anything
+    /// that isn't aligned. Once `room` is used up, nothing more gets written,
+    /// like at the end of a disk.
+    pub(in crate::writer_process) struct StrictWriter {
+        pub alignment: usize,
+        pub written: Vec<u8>,
+        pub room: usize,
+    }
+
+    impl Write for StrictWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !buf.len().is_multiple_of(self.alignment)
+                || !(buf.as_ptr() as usize).is_multiple_of(self.alignment)
+            {
+                return Err(io::ErrorKind::InvalidInput.into());
+            }
+            let buf = &buf[..buf.len().min(self.room - self.written.len())];
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test_case(512, 4096, 10_000; "smaller than buffer")]
+    #[test_case(512, 4096, 100_000; "many buffers")]
+    #[test_case(4096, 4096, 3 * 4096; "exact blocks")]
+    #[test_case(4096, 1000, 50_000; "buffer rounded up")]
+    fn writes_aligned_blocks(alignment: usize, capacity: usize, len: usize) {
+        let data: Vec<u8> = (0..len).map(|i| (i * 7 % 256) as u8).collect();
+        let mut w = AlignedWriter::new(
+            StrictWriter {
+                alignment,
+                written: vec![],
+                room: usize::MAX,
+            },
+            alignment,
+            capacity,
+        );
+
+        // Odd-sized chunks, with flushes in between, like a decompressor
+        // and the checkpoints would give.
+        for (i, chunk) in data.chunks(1234).enumerate() {
+            w.write_all(chunk).unwrap();
+            if i % 5 == 0 {
+                w.flush().unwrap();
+            }
+        }
+        let (inner, tail) = w.into_parts().unwrap();
+
+        assert!(inner.written.len().is_multiple_of(alignment));
+        assert_eq!(tail.len(), len % alignment);
+        assert_eq!([inner.written, tail].concat(), data);
+    }
+}
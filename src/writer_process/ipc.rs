@@ -1,19 +1,137 @@
 use std::{fmt::Display, path::PathBuf};
 
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 
 use valuable::Valuable;
 
+use crate::allowlist::AllowListError;
 use crate::compression::CompressionFormat;
-use crate::device::Type;
+use crate::device::{DeviceIdentity, Type};
+use crate::hash::{Digests, HashAlg};
+use crate::isomd5::ImplantedMd5;
+use crate::mounts::{Holder, Mount};
+use crate::multipart::ImageParts;
+use crate::ui::cli::{HashOf, SyncStrategy};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
 pub struct WriterProcessConfig {
     pub dest: PathBuf,
-    pub src: PathBuf,
+    pub src: ImageParts,
     pub verify: bool,
+    pub verify_strategy: VerifyStrategy,
     pub compression: CompressionFormat,
+    /// How writes to a block device are made to reach it.
+    pub sync_strategy: SyncStrategy,
     pub target_type: Type,
+    /// If provided, the input is hashed while it's being written, and the
+    /// write fails if any of the hashes do not match.
+    pub input_hash: Option<ExpectedHash>,
+    /// Algorithms to hash the decompressed input with while writing, only to
+    /// report the digests.
+    pub digests: Vec<HashAlg>,
+    /// If provided, the checksum implanted in the ISO is checked against the
+    /// disk while verifying.
+    pub implanted_md5: Option<ImplantedMd5>,
+    /// Filesystems and swap on the target to get out of use before writing.
+    pub unmount: Vec<Mount>,
+    /// Skips the check that the destination isn't the disk that the running
+    /// system is on.
+    pub allow_system_disk: bool,
+    /// Devices that the user knows are stacked on top of the destination.
+    /// Writing is refused if there are any others.
+    pub allowed_holders: Vec<Holder>,
+    /// What the destination was when the user confirmed it. If the device
+    /// that gets opened is any different, nothing is written.
+    pub expected_identity: Option<DeviceIdentity>,
+}
+
+/// How the disk is checked after writing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub enum VerifyStrategy {
+    /// Read the input again and compare it with the disk, block by block.
+    Compare,
+    /// Hash the data while writing it, then read back the disk and compare
+    /// the hashes. The input does not have to be decompressed again.
+    Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub struct ExpectedHash {
+    /// The digests to check, which are all computed in the same pass.
+    pub expected: Vec<(HashAlg, Vec<u8>)>,
+    /// Whether the hashes are of the decompressed data, or the input file as-is.
+    pub hash_of: HashOf,
+}
+
+impl ExpectedHash {
+    pub fn algs(&self) -> impl Iterator<Item = HashAlg> + '_ {
+        self.expected.iter().map(|(alg, _)| *alg)
+    }
+
+    /// Which of the decompressed and as-is input need to be hashed, in that
+    /// order. If the input is uncompressed, both are the same, so only the
+    /// first is.
+    pub fn hashed_streams(&self, cf: CompressionFormat) -> (bool, bool) {
+        match self.hash_of {
+            _ if cf.is_identity() => (true, false),
+            HashOf::Raw => (true, false),
+            HashOf::Compressed => (false, true),
+            HashOf::Auto => (true, true),
+        }
+    }
+
+    /// Checks the expected hashes against the hashes of the decompressed
+    /// (`raw`) and as-is (`compressed`) input. Either may be empty if that
+    /// stream wasn't hashed, but every expected algorithm must be in at least
+    /// one of them.
+    ///
+    /// Returns which stream each expected hash matched, or the first mismatch.
+    pub fn check(&self, raw: &Digests, compressed: &Digests) -> Result<Vec<InputHash>, ErrorType> {
+        let find = |hashes: &[(HashAlg, Vec<u8>)], alg: HashAlg| {
+            hashes
+                .iter()
+                .find(|(a, _)| *a == alg)
+                .map(|(_, h)| h.clone())
+        };
+
+        self.expected
+            .iter()
+            .map(|(alg, expected)| {
+                let candidates = [
+                    (HashOf::Raw, find(raw, *alg)),
+                    (HashOf::Compressed, find(compressed, *alg)),
+                ];
+                let mut candidates = candidates
+                    .into_iter()
+                    .filter_map(|(hash_of, hash)| Some((hash_of, hash?)));
+                let first = candidates
+                    .clone()
+                    .next()
+                    .expect("Every expected algorithm should have been hashed");
+
+                match candidates.find(|(_, hash)| hash == expected) {
+                    Some((hash_of, hash)) => Ok(InputHash {
+                        hash: ComputedHash { alg: *alg, hash },
+                        hash_of,
+                    }),
+                    None => Err(ErrorType::InputHashMismatch {
+                        alg: *alg,
+                        expected: expected.clone(),
+                        actual: first.1,
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A hash of the input, and which form of the input it was found to be of.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub struct InputHash {
+    pub hash: ComputedHash,
+    /// Either [HashOf::Raw] or [HashOf::Compressed].
+    pub hash_of: HashOf,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
@@ -32,10 +150,38 @@ pub enum StatusMessage {
         block_size: usize,
         duration_millis: u64,
     },
+    /// A digest of the decompressed input that the user asked for, computed
+    /// while writing. This is sent once for each algorithm.
+    ImageDigest(ComputedHash),
+    /// A hash of the input, computed while writing. This is sent once for each
+    /// expected hash.
+    InputHash(InputHash),
+    /// A hash of the written region of the device, computed while verifying.
+    /// This is sent once for each algorithm.
+    DeviceHash(ComputedHash),
+    /// The checksum implanted in the ISO matched the disk.
+    ImplantedMd5Verified,
     Success,
     Error(ErrorType),
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
+pub struct ComputedHash {
+    pub alg: HashAlg,
+    pub hash: Vec<u8>,
+}
+
+impl ComputedHash {
+    /// The hash in SRI format, i.e. `sha256-<base64>`.
+    pub fn sri(&self) -> String {
+        format!(
+            "{}-{}",
+            self.alg.sri_alg(),
+            base64::engine::general_purpose::STANDARD.encode(&self.hash)
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Valuable)]
 pub struct InitialInfo {
     pub input_file_bytes: u64,
@@ -46,19 +192,62 @@ pub enum ErrorType {
     EndOfOutput,
     PermissionDenied,
     VerificationFailed,
+    InputHashMismatch {
+        alg: HashAlg,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    DeviceHashMismatch {
+        alg: HashAlg,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    ImplantedMd5Mismatch {
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    SystemDisk,
+    ReadOnlyDevice,
+    TargetNotAllowed(String),
+    TargetChanged {
+        differences: Vec<String>,
+    },
+    DeviceBusy {
+        holders: Vec<String>,
+    },
+    UnmountFailed {
+        mount: String,
+        error: String,
+    },
     UnexpectedTermination,
     UnknownChildProcError(String),
 }
 
+impl From<AllowListError> for ErrorType {
+    fn from(value: AllowListError) -> Self {
+        Self::TargetNotAllowed(value.to_string())
+    }
+}
+
 impl From<std::io::Error> for ErrorType {
     fn from(value: std::io::Error) -> Self {
         match value.kind() {
             std::io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            std::io::ErrorKind::ReadOnlyFilesystem => Self::ReadOnlyDevice,
             _ => Self::UnknownChildProcError(format!("{value}")),
         }
     }
 }
 
+impl Display for VerifyStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyStrategy::Compare => write!(f, "compare with input"),
+            VerifyStrategy::Hash => write!(f, "hash"),
+        }
+    }
+}
+
 impl Display for ErrorType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -68,6 +257,82 @@ impl Display for ErrorType {
             ),
             ErrorType::PermissionDenied => write!(f, "Permission denied while opening file"),
             ErrorType::VerificationFailed => write!(f, "Disk verification failed!"),
+            ErrorType::InputHashMismatch {
+                alg,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "The {alg} hash of the input did not match! Your disk image may be corrupted!\n  \
+                     Expected: {}\n    Actual: {}",
+                    base16::encode_lower(expected),
+                    base16::encode_lower(actual)
+                )?;
+                // A bare hash of this length may have been assumed to be
+                // the wrong algorithm.
+                if let Some(other) = alg.same_length().first() {
+                    write!(
+                        f,
+                        "\nIf the expected hash isn't {alg}, give its algorithm too, like `{}-<hash>`.",
+                        other.sri_alg()
+                    )?;
+                }
+                Ok(())
+            }
+            ErrorType::DeviceHashMismatch {
+                alg,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The {alg} hash of the disk did not match the expected hash!\n  \
+                 Expected: {}\n    Actual: {}",
+                base16::encode_lower(expected),
+                base16::encode_lower(actual)
+            ),
+            ErrorType::ImplantedMd5Mismatch { expected, actual } => write!(
+                f,
+                "The checksum implanted in the ISO did not match the disk!\n  \
+                 Expected: {}\n    Actual: {}",
+                base16::encode_lower(expected),
+                base16::encode_lower(actual)
+            ),
+            ErrorType::SystemDisk => {
+                write!(
+                    f,
+                    "Refusing to write to the disk that the running system is on!"
+                )
+            }
+            ErrorType::ReadOnlyDevice => {
+                write!(
+                    f,
+                    "The target is read-only! If it's an SD card, check that the lock switch on its side is not set."
+                )
+            }
+            ErrorType::TargetNotAllowed(reason) => {
+                write!(f, "Refusing to write: {reason}")
+            }
+            ErrorType::TargetChanged { differences } => {
+                write!(
+                    f,
+                    "The target is not the device that was confirmed, refusing to write to it:"
+                )?;
+                for d in differences {
+                    write!(f, "\n  {d}")?;
+                }
+                Ok(())
+            }
+            ErrorType::DeviceBusy { holders } => {
+                write!(f, "Refusing to write to a device that is in use:")?;
+                for h in holders {
+                    write!(f, "\n  {h}")?;
+                }
+                Ok(())
+            }
+            ErrorType::UnmountFailed { mount, error } => {
+                write!(f, "Could not unmount {mount}: {error}")
+            }
             ErrorType::UnexpectedTermination => {
                 write!(f, "The child process unexpectedly terminated!")
             }
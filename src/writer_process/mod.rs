@@ -3,5 +3,6 @@
 //! IT IS NOT TO BE USED DIRECTLY BY THE USER! ITS API HAS NO STABILITY GUARANTEES!
 
 pub mod child;
+mod device_writer;
 pub mod ipc;
 mod xplat;
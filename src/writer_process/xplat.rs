@@ -3,43 +3,128 @@ use std::{
     path::Path,
 };
 
-use crate::compression::CompressionFormat;
+use crate::ui::cli::SyncStrategy;
 
+/// Opens a block device for writing. With [SyncStrategy::Direct], the file
+/// must only be written in whole blocks from aligned memory, until
+/// [disable_direct_io] is called.
 #[cfg(target_os = "linux")]
-pub fn open_blockdev(path: impl AsRef<Path>, cf: CompressionFormat) -> std::io::Result<File> {
+pub fn open_blockdev(path: impl AsRef<Path>, strategy: SyncStrategy) -> std::io::Result<File> {
     use std::os::unix::fs::OpenOptionsExt;
 
-    use libc::O_SYNC;
+    use libc::{O_DIRECT, O_SYNC};
 
     let mut opts = OpenOptions::new();
     opts.write(true);
-
-    // Decompression is a bigger bottleneck than write, so only bypass the
-    // cache if there is compression.
-    if cf.is_identity() {
-        opts.custom_flags(O_SYNC);
-    }
+    match strategy {
+        SyncStrategy::Direct => opts.custom_flags(O_DIRECT),
+        SyncStrategy::Sync => opts.custom_flags(O_SYNC),
+        SyncStrategy::Buffered => &mut opts,
+    };
 
     opts.open(path)
 }
 
 #[cfg(target_os = "macos")]
-pub fn open_blockdev(path: impl AsRef<Path>, _cf: CompressionFormat) -> std::io::Result<File> {
+pub fn open_blockdev(path: impl AsRef<Path>, strategy: SyncStrategy) -> std::io::Result<File> {
     // For more info, see:
     // https://stackoverflow.com/questions/2299402/how-does-one-do-raw-io-on-mac-os-x-ie-equivalent-to-linuxs-o-direct-flag
 
     use libc::{fcntl, F_NOCACHE, O_SYNC};
     use std::os::{fd::AsRawFd, unix::fs::OpenOptionsExt};
 
-    let file = OpenOptions::new()
-        .write(true)
-        .custom_flags(O_SYNC)
-        .open(path)?;
+    let mut opts = OpenOptions::new();
+    opts.write(true);
+    if strategy != SyncStrategy::Buffered {
+        opts.custom_flags(O_SYNC);
+    }
+    let file = opts.open(path)?;
 
-    #[cfg(target_os = "macos")]
-    unsafe {
-        fcntl(file.as_raw_fd().into(), F_NOCACHE);
+    // F_NOCACHE doesn't need aligned writes, unlike O_DIRECT.
+    if strategy != SyncStrategy::Buffered {
+        unsafe {
+            fcntl(file.as_raw_fd(), F_NOCACHE, 1);
+        }
     }
 
     Ok(file)
 }
+
+/// How writes to a block device opened with [SyncStrategy::Direct] need to be
+/// aligned, going by its logical and physical block sizes. [None] means that
+/// they don't need to be.
+#[cfg(target_os = "linux")]
+pub fn direct_io_alignment(file: &File) -> std::io::Result<Option<usize>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let rdev = file.metadata()?.rdev();
+    let mut sysnode = std::path::PathBuf::from(format!(
+        "/sys/dev/block/{}:{}",
+        libc::major(rdev),
+        libc::minor(rdev)
+    ))
+    .canonicalize()?;
+    // Partitions don't have a queue of their own.
+    if sysnode.join("partition").exists() {
+        sysnode.pop();
+    }
+
+    let read = |name: &str| -> std::io::Result<usize> {
+        let value = std::fs::read_to_string(sysnode.join("queue").join(name))?;
+        value
+            .trim()
+            .parse()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    };
+    // Writing whole physical blocks also saves the disk from having to read
+    // them back in to change part of them.
+    Ok(Some(
+        read("logical_block_size")?.max(read("physical_block_size")?),
+    ))
+}
+
+#[cfg(target_os = "macos")]
+pub fn direct_io_alignment(_file: &File) -> std::io::Result<Option<usize>> {
+    Ok(None)
+}
+
+/// Goes back to writing through the page cache, for writes that aren't
+/// whole blocks.
+#[cfg(target_os = "linux")]
+pub fn disable_direct_io(file: &File) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn disable_direct_io(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Whether the kernel refuses writes to the block device, like when the lock
+/// switch on an SD card is set.
+#[cfg(target_os = "linux")]
+pub fn is_read_only(file: &File) -> std::io::Result<bool> {
+    use std::os::fd::AsRawFd;
+
+    // _IO(0x12, 94), from linux/fs.h.
+    const BLKROGET: libc::Ioctl = 0x125e;
+
+    let mut ro: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKROGET, &mut ro) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(ro != 0)
+}
+
+/// Opening a read-only disk for writing already fails here.
+#[cfg(target_os = "macos")]
+pub fn is_read_only(_file: &File) -> std::io::Result<bool> {
+    Ok(false)
+}